@@ -0,0 +1,139 @@
+use std::mem;
+
+/// A line that leaves the comment it is in untouched.
+const IGNORE_MARKER: &str = "dtsfmt-ignore";
+
+/// Reflows a run of `//` comments to fit in `width` columns, returning the
+/// resulting comment lines. Paragraphs are separated by empty comment lines,
+/// and paragraphs that look like code, tables or diagrams are kept exactly as
+/// written, as is the whole run if it contains a `// dtsfmt-ignore` line.
+pub fn reflow_comments(comments: &[&str], width: usize) -> Vec<String> {
+    let verbatim = |comment: &&str| comment.trim_end().to_owned();
+
+    if comments.iter().any(|comment| text(comment) == IGNORE_MARKER) {
+        return comments.iter().map(verbatim).collect();
+    }
+
+    let mut output = Vec::new();
+    let paragraphs = comments.split(|comment| text(comment).is_empty());
+
+    for (i, paragraph) in paragraphs.enumerate() {
+        if i > 0 {
+            output.push("//".to_owned());
+        }
+
+        match paragraph.iter().any(|comment| is_preformatted(text(comment))) {
+            true => output.extend(paragraph.iter().map(verbatim)),
+            false => {
+                let lines =
+                    paragraph.iter().map(|c| text(c)).collect::<Vec<_>>();
+                output.extend(
+                    fill(&lines, width)
+                        .iter()
+                        .map(|line| format!("// {}", line)),
+                );
+            }
+        }
+    }
+
+    output
+}
+
+/// The text of a `//` comment.
+fn text(comment: &str) -> &str {
+    comment.trim_start_matches("//").trim()
+}
+
+/// Fills lines with as many words as fit in `width` columns. Words that are
+/// longer than that, like URLs, get a line of their own.
+fn fill(paragraph: &[&str], width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+
+    for word in paragraph.iter().flat_map(|line| line.split_whitespace()) {
+        let len = line.chars().count() + 1 + word.chars().count();
+
+        if !line.is_empty() && len > width {
+            lines.push(mem::take(&mut line));
+        }
+
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Whether the line looks like code, a `dtsfmt:` pragma, a list item, a table
+/// or a diagram, whose line breaks and spacing matter.
+fn is_preformatted(line: &str) -> bool {
+    let code = line.ends_with([';', '{', '}', '\\'])
+        || line.starts_with(['#', '@'])
+        || line.starts_with("dtsfmt")
+        || line.contains(" = ")
+        || line.contains('<') && line.contains('>')
+        || line.split_whitespace().any(|word| {
+            word.len() > 1 && word.starts_with('&') && !word.starts_with("&&")
+        });
+
+    let list = line.starts_with(['-', '*', '+'])
+        || line
+            .split_once(['.', ')'])
+            .is_some_and(|(n, _)| n.parse::<usize>().is_ok());
+
+    let table = line.contains("  ")
+        || line.contains(['|', '`', '\t'])
+        || line.contains("--")
+        || line.contains("==")
+        || line.chars().any(|c| ('\u{2500}'..='\u{259f}').contains(&c));
+
+    code || list || table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reflows_paragraphs() {
+        let comments = [
+            "// Home row mods are hold-taps on the home row,",
+            "//which act as modifiers when held.",
+            "//",
+            "// See https://example.com/a/very/long/url/that/does/not/fit.",
+        ];
+
+        assert_eq!(
+            reflow_comments(&comments, 30),
+            vec![
+                "// Home row mods are hold-taps on",
+                "// the home row, which act as",
+                "// modifiers when held.",
+                "//",
+                "// See",
+                "// https://example.com/a/very/long/url/that/does/not/fit.",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_keeps_preformatted_paragraphs() {
+        let comments =
+            ["// Layers:", "//   | Q | W |", "", "// short", "// lines"];
+
+        assert_eq!(
+            reflow_comments(&comments, 80),
+            vec!["// Layers:", "//   | Q | W |", "//", "// short lines"]
+        );
+
+        let comments = ["// dtsfmt-ignore", "//  short", "// lines"];
+        assert_eq!(reflow_comments(&comments, 80), comments);
+        assert_eq!(reflow_comments(&["// bindings = <&kp A>;"], 8).len(), 1);
+    }
+}
@@ -1,18 +1,216 @@
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
 use serde::Deserialize;
 use typed_builder::TypedBuilder;
 
-use crate::layouts::KeyboardLayoutType;
+use crate::layouts::{
+    detect_layout, find_builtin, resolve_layout, AutoLayout, CustomLayout,
+    Detection, Document, KeyboardLayout, LayoutConfig, LayoutMode,
+};
 
 mod constants;
+pub mod pragma;
 
-#[derive(Default, Deserialize, TypedBuilder)]
+/// What to do with a keymap layer whose number of bindings doesn't match the
+/// number of keys in the layout.
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BindingMismatch {
+    /// Leave the bindings as they were written and emit a warning.
+    #[default]
+    Verbatim,
+    /// Refuse to format the file.
+    Error,
+}
+
+/// How the child nodes of a node are ordered.
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortNodes {
+    /// Keep the nodes in the order they were written.
+    #[default]
+    Preserve,
+    /// Sort the nodes by their unit address, e.g. `uart@40002000`.
+    UnitAddress,
+}
+
+/// The case of the digits of hexadecimal numbers.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HexCase {
+    /// Keep the digits as written.
+    #[default]
+    Preserve,
+    Lower,
+    Upper,
+}
+
+/// The radix that the integers in the cells of a property are written in.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Radix {
+    Hex,
+    Decimal,
+}
+
+/// How many bytes of a byte string are printed on each line.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum ByteStringColumns {
+    /// A fixed number of bytes on each line.
+    Count(usize),
+    Layout(ByteStringLayout),
+}
+
+impl Default for ByteStringColumns {
+    fn default() -> Self {
+        Self::Count(16)
+    }
+}
+
+/// The layouts of byte strings that don't have a fixed number of bytes on
+/// each line.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ByteStringLayout {
+    /// As many bytes on each line as fit in `max_width`.
+    Width,
+    /// The bytes on each line as they were written.
+    Preserve,
+}
+
+/// How the arguments of a macro call are formatted.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MacroKind {
+    /// The arguments are the properties and child nodes of a node, as in
+    /// `ZMK_HOLD_TAP(name, flavor = "balanced"; ...)`.
+    Properties,
+    /// The arguments are the bindings of a keymap layer, as in
+    /// `ZMK_LAYER(name, &kp Q &kp W ...)`.
+    Bindings,
+}
+
+/// A macro whose calls generate nodes, such as the helpers of zmk-helpers.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MacroConfig {
+    pub kind: MacroKind,
+    /// The number of arguments before the properties or bindings, e.g. the
+    /// name of the behavior.
+    #[serde(default)]
+    pub args: usize,
+}
+
+/// The macros of zmk-helpers, which are recognized unless they are configured
+/// otherwise.
+fn builtin_macro(name: &str) -> Option<MacroConfig> {
+    let (kind, args) = match name {
+        "ZMK_LAYER" => (MacroKind::Bindings, 1),
+        "ZMK_BEHAVIOR" => (MacroKind::Properties, 2),
+        "ZMK_HOLD_TAP" | "ZMK_MOD_MORPH" | "ZMK_TAP_DANCE" | "ZMK_MACRO" => {
+            (MacroKind::Properties, 1)
+        }
+        _ => return None,
+    };
+
+    Some(MacroConfig { kind, args })
+}
+
+/// Options that can be changed for some files, either by an `[[overrides]]`
+/// section of the config file or by a `// dtsfmt:` comment in the file.
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Options {
+    pub layout: Option<LayoutConfig>,
+    pub indent_str: Option<String>,
+    pub warn_on_unhandled_tokens: Option<bool>,
+    pub binding_mismatch: Option<BindingMismatch>,
+    pub verify_tokens: Option<bool>,
+    pub align_trailing_comments: Option<bool>,
+    pub normalize_comment_gutters: Option<bool>,
+    pub comment_width: Option<usize>,
+    pub max_width: Option<usize>,
+    pub max_blank_lines: Option<usize>,
+    pub sort_properties: Option<bool>,
+    pub property_order: Option<Vec<String>>,
+    pub sort_nodes: Option<SortNodes>,
+    pub hex_case: Option<HexCase>,
+    pub radix: Option<BTreeMap<String, Radix>>,
+    pub byte_string_columns: Option<ByteStringColumns>,
+    pub byte_string_group: Option<usize>,
+}
+
+/// Options that apply to the files matching any of the given globs, which are
+/// relative to the directory of the config file.
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "OverrideTable")]
+pub struct Override {
+    pub files: Vec<String>,
+    pub options: Options,
+    globs: GlobSet,
+}
+
+/// An `[[overrides]]` table as written. The options are deserialized
+/// separately since unknown fields can't be denied through a flattened
+/// struct.
+#[derive(Deserialize)]
+struct OverrideTable {
+    files: Vec<String>,
+    #[serde(flatten)]
+    options: toml::Table,
+}
+
+impl TryFrom<OverrideTable> for Override {
+    type Error = String;
+
+    fn try_from(table: OverrideTable) -> Result<Self, Self::Error> {
+        let options = Options::deserialize(toml::Value::Table(table.options))
+            .map_err(|err| err.message().to_owned())?;
+
+        let mut globs = GlobSetBuilder::new();
+        for pattern in &table.files {
+            globs.add(build_glob(pattern).map_err(|err| {
+                format!("invalid glob `{}`: {}", pattern, err)
+            })?);
+        }
+        let globs = globs.build().map_err(|err| err.to_string())?;
+
+        Ok(Self { files: table.files, options, globs })
+    }
+}
+
+impl Override {
+    fn matches(&self, path: &Path) -> bool {
+        self.globs.is_match(path)
+    }
+}
+
+/// Builds a glob where `*` doesn't match across directories but `**` does.
+fn build_glob(pattern: &str) -> Result<Glob, globset::Error> {
+    GlobBuilder::new(pattern).literal_separator(true).build()
+}
+
+#[derive(Clone, Deserialize, TypedBuilder)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    #[builder(default, setter(into))]
+    #[serde(default)]
+    pub layout: LayoutConfig,
+
+    /// Custom layouts that can be referenced by name in `layout`.
+    #[builder(default)]
+    #[serde(default)]
+    pub layouts: BTreeMap<String, CustomLayout>,
+
+    /// Rules that pick a layout for the files they match when the layout is
+    /// `auto`, which take precedence over the bundled rules.
     #[builder(default)]
     #[serde(default)]
-    pub layout: KeyboardLayoutType,
+    pub auto_layouts: Vec<AutoLayout>,
 
     #[builder(default_code = "Config::default_indent_str()")]
     #[serde(default = "Config::default_indent_str")]
@@ -21,22 +219,325 @@ pub struct Config {
     #[builder(default)]
     #[serde(default)]
     pub warn_on_unhandled_tokens: bool,
+
+    #[builder(default)]
+    #[serde(default)]
+    pub binding_mismatch: BindingMismatch,
+
+    /// Re-parse the formatted output and refuse to write it if any
+    /// significant tokens were changed.
+    #[builder(default_code = "Config::default_verify_tokens()")]
+    #[serde(default = "Config::default_verify_tokens")]
+    pub verify_tokens: bool,
+
+    /// Align the comments at the end of consecutive lines to the same column.
+    #[builder(default)]
+    #[serde(default)]
+    pub align_trailing_comments: bool,
+
+    /// Rewrite the ` * ` at the start of each line of a block comment so that
+    /// the stars line up under the first star of `/*`.
+    #[builder(default)]
+    #[serde(default)]
+    pub normalize_comment_gutters: bool,
+
+    /// Reflow paragraphs of `//` comments to fit in this many columns.
+    #[builder(default, setter(strip_option))]
+    #[serde(default)]
+    pub comment_width: Option<usize>,
+
+    /// Break property values that don't fit in this many columns across
+    /// lines.
+    #[builder(default, setter(strip_option))]
+    #[serde(default)]
+    pub max_width: Option<usize>,
+
+    /// The most blank lines kept between statements. Blank lines after `{`
+    /// and before `}` are always removed.
+    #[builder(default_code = "Config::default_max_blank_lines()")]
+    #[serde(default = "Config::default_max_blank_lines")]
+    pub max_blank_lines: usize,
+
+    /// Sort the properties of each node, starting with `compatible`, `reg`,
+    /// `ranges` and `#*-cells` and ending with `status`.
+    #[builder(default)]
+    #[serde(default)]
+    pub sort_properties: bool,
+
+    /// The order of the properties after the ones that always come first
+    /// when sorting. Properties that aren't listed are sorted alphabetically
+    /// after these.
+    #[builder(default)]
+    #[serde(default)]
+    pub property_order: Vec<String>,
+
+    /// Sort the child nodes of each node by their unit address.
+    #[builder(default)]
+    #[serde(default)]
+    pub sort_nodes: SortNodes,
+
+    /// The case of hexadecimal digits in cells, byte strings and unit
+    /// addresses.
+    #[builder(default)]
+    #[serde(default)]
+    pub hex_case: HexCase,
+
+    /// The radix of the integers in the cells of each property, by the name
+    /// of the property.
+    #[builder(default)]
+    #[serde(default)]
+    pub radix: BTreeMap<String, Radix>,
+
+    /// How many bytes of a byte string are printed on each line.
+    #[builder(default)]
+    #[serde(default)]
+    pub byte_string_columns: ByteStringColumns,
+
+    /// The number of bytes of a byte string printed together as a word,
+    /// without spaces between them.
+    #[builder(default_code = "Config::default_byte_string_group()")]
+    #[serde(default = "Config::default_byte_string_group")]
+    pub byte_string_group: usize,
+
+    /// Macros whose calls generate nodes, in addition to the zmk-helpers
+    /// macros that are recognized by default.
+    #[builder(default)]
+    #[serde(default)]
+    pub macros: BTreeMap<String, MacroConfig>,
+
+    /// Options for specific files, applied in order so that later overrides
+    /// take precedence.
+    #[builder(default)]
+    #[serde(default)]
+    pub overrides: Vec<Override>,
+
+    /// The directory containing the config file.
+    #[builder(default, setter(strip_option))]
+    #[serde(skip)]
+    pub root: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::builder().build()
+    }
 }
 
 impl Config {
-    pub fn parse(cwd: &Path) -> Self {
-        if let Some(rc_file) = find_rc_file(cwd) {
-            let buf = fs::read_to_string(rc_file)
-                .expect("Failed to read config file");
-            toml::from_str(&buf).expect("Failed to parse config file")
-        } else {
-            Self::default()
+    pub fn parse(cwd: &Path) -> Result<Self, String> {
+        let Some(rc_file) = find_rc_file(cwd) else {
+            return Ok(Self::default());
+        };
+
+        let buf =
+            fs::read_to_string(&rc_file).expect("Failed to read config file");
+        let mut config: Self = toml::from_str(&buf).map_err(|err| {
+            format!("Failed to parse {}: {}", rc_file.display(), err)
+        })?;
+
+        if let Some(dir) = rc_file.parent() {
+            config.resolve_paths(dir);
+            config.root =
+                Some(fs::canonicalize(dir).unwrap_or_else(|_| dir.into()));
+        }
+
+        config.validate().map_err(|err| {
+            format!("Invalid config in {}: {}", rc_file.display(), err)
+        })?;
+
+        Ok(config)
+    }
+
+    /// Resolves the paths of layout files relative to the config file.
+    fn resolve_paths(&mut self, dir: &Path) {
+        if let LayoutConfig::Inline(layout) = &mut self.layout {
+            layout.resolve_paths(dir);
+        }
+
+        for layout in self.layouts.values_mut() {
+            layout.resolve_paths(dir);
+        }
+
+        for override_ in &mut self.overrides {
+            if let Some(LayoutConfig::Inline(layout)) =
+                &mut override_.options.layout
+            {
+                layout.resolve_paths(dir);
+            }
+        }
+    }
+
+    /// Checks that the custom layouts and the selected layout are valid so
+    /// that mistakes are reported upfront rather than producing a misaligned
+    /// bindings table.
+    pub fn validate(&self) -> Result<(), String> {
+        // Custom layouts may reuse the name of a bundled layout, which they
+        // then take the place of.
+        for (name, layout) in &self.layouts {
+            layout
+                .to_layout()
+                .map_err(|err| format!("invalid layout `{}`: {}", name, err))?;
+        }
+
+        for override_ in &self.overrides {
+            if let Some(layout) = &override_.options.layout {
+                self.validate_layout(layout)?;
+            }
+        }
+
+        for auto in &self.auto_layouts {
+            self.validate_layout(&LayoutConfig::Named(auto.layout.clone()))?;
+        }
+
+        self.validate_layout(&self.layout)
+    }
+
+    /// Checks that the given layout refers to an existing custom layout or is
+    /// a valid inline layout.
+    pub fn validate_layout(&self, layout: &LayoutConfig) -> Result<(), String> {
+        // Layouts derived from the file being formatted can only be checked
+        // once we have the file.
+        match layout {
+            LayoutConfig::Named(name)
+                if !self.layouts.contains_key(name)
+                    && find_builtin(name).is_none() =>
+            {
+                Err(format!("unknown layout `{}`", name))
+            }
+            LayoutConfig::Inline(layout) => layout
+                .to_layout()
+                .map(|_| ())
+                .map_err(|err| format!("invalid layout: {}", err)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns a copy of the config with the given options applied.
+    pub fn with_options(&self, options: &Options) -> Self {
+        let mut config = self.clone();
+
+        if let Some(layout) = &options.layout {
+            config.layout = layout.clone();
+        }
+        if let Some(indent_str) = &options.indent_str {
+            config.indent_str = indent_str.clone();
+        }
+        if let Some(warn) = options.warn_on_unhandled_tokens {
+            config.warn_on_unhandled_tokens = warn;
+        }
+        if let Some(binding_mismatch) = options.binding_mismatch {
+            config.binding_mismatch = binding_mismatch;
+        }
+        if let Some(verify_tokens) = options.verify_tokens {
+            config.verify_tokens = verify_tokens;
+        }
+        if let Some(align) = options.align_trailing_comments {
+            config.align_trailing_comments = align;
+        }
+        if let Some(normalize) = options.normalize_comment_gutters {
+            config.normalize_comment_gutters = normalize;
+        }
+        if let Some(width) = options.comment_width {
+            config.comment_width = Some(width);
+        }
+        if let Some(width) = options.max_width {
+            config.max_width = Some(width);
+        }
+        if let Some(lines) = options.max_blank_lines {
+            config.max_blank_lines = lines;
+        }
+        if let Some(sort) = options.sort_properties {
+            config.sort_properties = sort;
         }
+        if let Some(order) = &options.property_order {
+            config.property_order = order.clone();
+        }
+        if let Some(sort) = options.sort_nodes {
+            config.sort_nodes = sort;
+        }
+        if let Some(case) = options.hex_case {
+            config.hex_case = case;
+        }
+        if let Some(radix) = &options.radix {
+            config.radix = radix.clone();
+        }
+        if let Some(columns) = options.byte_string_columns {
+            config.byte_string_columns = columns;
+        }
+        if let Some(group) = options.byte_string_group {
+            config.byte_string_group = group;
+        }
+
+        config
+    }
+
+    /// Returns the config for the given file with the options of every
+    /// matching override applied.
+    pub fn for_file(&self, path: &Path) -> Self {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.into());
+        let relative = self
+            .root
+            .as_deref()
+            .and_then(|root| path.strip_prefix(root).ok())
+            .unwrap_or(&path);
+
+        self.overrides
+            .iter()
+            .filter(|override_| override_.matches(relative))
+            .fold(self.clone(), |config, override_| {
+                config.with_options(&override_.options)
+            })
+    }
+
+    /// Picks the layout for the given document if the layout is `auto`.
+    pub fn detect_layout(&self, document: &Document) -> Option<Detection> {
+        match self.layout {
+            LayoutConfig::Mode(LayoutMode::Auto) => {
+                detect_layout(&self.auto_layouts, &self.layouts, document)
+            }
+            _ => None,
+        }
+    }
+
+    /// Resolves the configured or detected keyboard layout for the given
+    /// document, or `None` if the rows of the bindings should be kept as
+    /// written.
+    pub fn keyboard_layout(
+        &self,
+        document: &Document,
+        detection: Option<&Detection>,
+    ) -> Result<Option<KeyboardLayout>, String> {
+        match detection {
+            Some(detection) => resolve_layout(
+                &LayoutConfig::from(detection.layout.as_str()),
+                &self.layouts,
+                document,
+            ),
+            None => resolve_layout(&self.layout, &self.layouts, document),
+        }
+    }
+
+    /// Finds how calls of the given macro are formatted, if it generates
+    /// nodes.
+    pub fn macro_config(&self, name: &str) -> Option<MacroConfig> {
+        self.macros.get(name).copied().or_else(|| builtin_macro(name))
     }
 
     pub fn default_indent_str() -> String {
         "  ".to_owned()
     }
+
+    pub fn default_verify_tokens() -> bool {
+        true
+    }
+
+    pub fn default_max_blank_lines() -> usize {
+        1
+    }
+
+    pub fn default_byte_string_group() -> usize {
+        1
+    }
 }
 
 fn find_rc_file(path: &Path) -> Option<PathBuf> {
@@ -63,3 +564,88 @@ fn find_rc_file(path: &Path) -> Option<PathBuf> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layouts::resolve_named;
+
+    #[test]
+    fn it_applies_matching_overrides() {
+        let mut config: Config = toml::from_str(
+            r#"
+            layout = "sweep"
+
+            [[overrides]]
+            files = ["boards/glove80/**"]
+            layout = "moergo:glove80"
+            indent_str = "    "
+
+            [[overrides]]
+            files = ["*.keymap"]
+            verify_tokens = false
+            "#,
+        )
+        .unwrap();
+        config.root = Some(PathBuf::from("/repo"));
+        assert!(config.validate().is_ok());
+
+        let glove80 =
+            config.for_file(Path::new("/repo/boards/glove80/glove80.keymap"));
+        assert!(
+            matches!(glove80.layout, LayoutConfig::Named(ref name) if name == "moergo:glove80")
+        );
+        assert_eq!(glove80.indent_str, "    ");
+        assert!(glove80.verify_tokens);
+
+        let sweep = config.for_file(Path::new("/repo/sweep.keymap"));
+        assert!(
+            matches!(sweep.layout, LayoutConfig::Named(ref name) if name == "sweep")
+        );
+        assert_eq!(sweep.indent_str, "  ");
+        assert!(!sweep.verify_tokens);
+    }
+
+    #[test]
+    fn it_lets_custom_layouts_replace_bundled_layouts() {
+        let config: Config = toml::from_str(
+            r#"
+            layout = "corne"
+
+            [layouts.corne]
+            grid = [[1, 1, 0, 1, 1]]
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+
+        let layout = resolve_named("corne", &config.layouts).unwrap();
+        assert_eq!(layout.key_count(), 4);
+    }
+
+    #[test]
+    fn it_rejects_invalid_overrides() {
+        let config: Config = toml::from_str(
+            r#"
+            [[overrides]]
+            files = ["boards/**"]
+            layout = "unknown"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+
+        for table in [
+            "files = [\"boards/**\"]\nindent_sr = \"    \"",
+            "files = [\"boards/[\"]",
+        ] {
+            let toml = format!("[[overrides]]\n{}", table);
+            assert!(toml::from_str::<Config>(&toml).is_err());
+        }
+
+        assert!(toml::from_str::<Config>("layot = \"sweep\"").is_err());
+
+        let err = toml::from_str::<Config>("layout = { gird = [[1, 1]] }");
+        assert!(err.is_err_and(|err| err.message().contains("`gird`")));
+    }
+}
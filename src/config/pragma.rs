@@ -0,0 +1,146 @@
+use tree_sitter::{Node, Tree};
+
+use super::{Config, Options};
+use crate::diagnostics::Diagnostic;
+
+const PREFIX: &str = "dtsfmt:";
+
+/// Applies the options of every `// dtsfmt: key=value` comment in the file to
+/// the config, so that a file can select e.g. its own layout.
+pub fn apply_pragmas(
+    config: &Config,
+    tree: &Tree,
+    source: &str,
+) -> Result<Config, Diagnostic> {
+    let mut comments = Vec::new();
+    collect_comments(tree.root_node(), &mut comments);
+
+    let mut config = config.clone();
+    for comment in comments {
+        let text = comment.utf8_text(source.as_bytes()).unwrap_or("");
+        let Some(pragma) = pragma_text(text) else {
+            continue;
+        };
+
+        config = parse_pragma(pragma)
+            .and_then(|options| {
+                let config = config.with_options(&options);
+                config.validate_layout(&config.layout)?;
+                Ok(config)
+            })
+            .map_err(|err| {
+                Diagnostic::at_node(
+                    source,
+                    &comment,
+                    format!("invalid dtsfmt pragma: {}", err),
+                )
+            })?;
+    }
+
+    Ok(config)
+}
+
+/// Returns the text after `dtsfmt:` if the comment is a pragma.
+fn pragma_text(comment: &str) -> Option<&str> {
+    let text = match comment.strip_prefix("//") {
+        Some(text) => text,
+        None => comment.strip_prefix("/*")?.strip_suffix("*/")?,
+    };
+
+    text.trim().strip_prefix(PREFIX)
+}
+
+/// Parses the whitespace separated `key=value` pairs of a pragma. Values can
+/// be written as TOML values, or unquoted if they are plain strings.
+fn parse_pragma(pragma: &str) -> Result<Options, String> {
+    let mut lines = Vec::new();
+
+    for pair in split_pairs(pragma) {
+        let (key, value) = pair.split_once('=').ok_or_else(|| {
+            format!("expected `key=value` but found `{}`", pair)
+        })?;
+        let key = key.trim();
+
+        let value = value.trim();
+        let value =
+            match toml::from_str::<toml::Table>(&format!("v = {}", value)) {
+                Ok(_) => value.to_owned(),
+                Err(_) => format!("{:?}", value),
+            };
+
+        lines.push(format!("{} = {}", key, value));
+    }
+
+    toml::from_str(&lines.join("\n")).map_err(|err| err.message().to_owned())
+}
+
+/// Splits a pragma on whitespace that isn't inside a quoted value.
+fn split_pairs(pragma: &str) -> Vec<&str> {
+    let mut pairs = Vec::new();
+    let mut start = None;
+    let mut quote = None;
+
+    for (i, c) in pragma.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (c, None) if c.is_whitespace() => {
+                if let Some(start) = start.take() {
+                    pairs.push(&pragma[start..i]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        start.get_or_insert(i);
+    }
+
+    if let Some(start) = start {
+        pairs.push(&pragma[start..]);
+    }
+
+    pairs
+}
+
+fn collect_comments<'a>(node: Node<'a>, comments: &mut Vec<Node<'a>>) {
+    let mut cursor = node.walk();
+
+    for child in node.children(&mut cursor) {
+        if child.kind() == "comment" {
+            comments.push(child);
+        } else {
+            collect_comments(child, comments);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layouts::LayoutConfig;
+
+    #[test]
+    fn it_parses_pragmas() {
+        let pragma = pragma_text("// dtsfmt: layout=sweep indent_str=\"\t\"");
+        let options = parse_pragma(pragma.unwrap()).unwrap();
+
+        assert!(
+            matches!(options.layout, Some(LayoutConfig::Named(ref name)) if name == "sweep")
+        );
+        assert_eq!(options.indent_str.as_deref(), Some("\t"));
+
+        let options = parse_pragma(" verify_tokens=false").unwrap();
+        assert_eq!(options.verify_tokens, Some(false));
+
+        assert!(pragma_text("/* dtsfmt: layout=sweep */").is_some());
+        assert!(pragma_text("// not a pragma").is_none());
+    }
+
+    #[test]
+    fn it_rejects_invalid_pragmas() {
+        assert!(parse_pragma("layout").is_err());
+        assert!(parse_pragma("indent=4").is_err());
+        assert!(parse_pragma("verify_tokens=maybe").is_err());
+    }
+}
@@ -1,10 +1,19 @@
+use std::cell::RefCell;
+
 use crate::config::Config;
+use crate::diagnostics::Diagnostic;
+use crate::layouts::KeyboardLayout;
 
 pub struct Context<'a> {
     pub indent: usize,
     pub keymap: bool,
     pub bindings: bool,
     pub config: &'a Config,
+    /// The layout of keymap bindings, or `None` to keep the rows as written.
+    pub layout: &'a Result<Option<KeyboardLayout>, String>,
+    /// The column sizes of keymap bindings whose rows are kept as written.
+    pub column_sizes: &'a [usize],
+    pub diagnostics: &'a RefCell<Vec<Diagnostic>>,
 }
 
 impl Context<'_> {
@@ -12,10 +21,6 @@ impl Context<'_> {
         Self { indent: self.indent + increment, ..*self }
     }
 
-    pub fn dec(&self, decrement: usize) -> Self {
-        Self { indent: self.indent - decrement, ..*self }
-    }
-
     pub fn keymap(&self) -> Self {
         Self { keymap: true, ..*self }
     }
@@ -30,4 +35,8 @@ impl Context<'_> {
     pub fn has_zephyr_syntax(&self) -> bool {
         self.bindings && self.keymap
     }
+
+    pub fn report(&self, diagnostic: Diagnostic) {
+        self.diagnostics.borrow_mut().push(diagnostic);
+    }
 }
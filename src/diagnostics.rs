@@ -0,0 +1,85 @@
+use std::fmt::{self, Display};
+
+use tree_sitter::Node;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The file can't be safely formatted and is left untouched.
+    Error,
+    /// The file is formatted, but part of it was left as is.
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found while formatting a file, pointing at a location in the
+/// original source.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column number.
+    pub column: usize,
+    pub message: String,
+    /// The source line the diagnostic points at.
+    pub snippet: String,
+}
+
+impl Diagnostic {
+    pub fn new(
+        source: &str,
+        line: usize,
+        column: usize,
+        message: String,
+    ) -> Self {
+        let snippet =
+            source.lines().nth(line - 1).unwrap_or_default().to_owned();
+
+        Self { severity: Severity::Error, line, column, message, snippet }
+    }
+
+    /// Creates a diagnostic pointing at the start of the given node.
+    pub fn at_node(source: &str, node: &Node, message: String) -> Self {
+        let position = node.start_position();
+
+        Self::new(source, position.row + 1, position.column + 1, message)
+    }
+
+    pub fn with_severity(self, severity: Severity) -> Self {
+        Self { severity, ..self }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}:{}: {}: {}",
+            self.line, self.column, self.severity, self.message
+        )?;
+
+        // Keep tabs in the caret line so it lines up with the snippet.
+        let caret_indent = self
+            .snippet
+            .get(..self.column - 1)
+            .unwrap_or(&self.snippet)
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+
+        writeln!(f, "  {}", self.snippet)?;
+        write!(f, "  {}^", caret_indent)
+    }
+}
@@ -0,0 +1,461 @@
+/// A document describing the layout of formatted code, after Wadler's "A
+/// prettier printer". Documents are built from the syntax tree and rendered
+/// to text by [`render`], which decides which line breaks to print based on
+/// the width of the output.
+#[derive(Clone, Debug)]
+pub enum Doc {
+    /// Text printed as is. The lines after the first of text that contains
+    /// line breaks are printed verbatim rather than indented.
+    Text(String),
+    /// Documents printed one after the other.
+    Concat(Vec<Doc>),
+    /// A space, or a line break if the enclosing group doesn't fit.
+    Line,
+    /// Nothing, or a line break if the enclosing group doesn't fit.
+    SoftLine,
+    /// A line break that is always printed.
+    HardLine,
+    /// Indents the lines of the document by one more level.
+    Indent(Box<Doc>),
+    /// Indents the lines of the document to the column where it starts.
+    Hang(Box<Doc>),
+    /// Prints the lines of the document as spaces if it fits on the rest of
+    /// the line, or as line breaks otherwise.
+    Group(Box<Doc>),
+    /// Contents separated by lines, where each line is only broken if the
+    /// content after it doesn't fit on the rest of the line. The contents are
+    /// at the even indices and the separating lines at the odd indices.
+    Fill(Vec<Doc>),
+    /// A document printed at the end of the line, before the next line break,
+    /// such as a trailing comment.
+    LineSuffix(Box<Doc>),
+    /// Spaces up to the given column, if the line is shorter than that.
+    PadTo(usize),
+}
+
+pub fn text(text: impl Into<String>) -> Doc {
+    Doc::Text(text.into())
+}
+
+pub fn concat(docs: Vec<Doc>) -> Doc {
+    Doc::Concat(docs)
+}
+
+pub fn indent(doc: Doc) -> Doc {
+    Doc::Indent(Box::new(doc))
+}
+
+pub fn hang(doc: Doc) -> Doc {
+    Doc::Hang(Box::new(doc))
+}
+
+pub fn group(doc: Doc) -> Doc {
+    Doc::Group(Box::new(doc))
+}
+
+pub fn line_suffix(doc: Doc) -> Doc {
+    Doc::LineSuffix(Box::new(doc))
+}
+
+/// Fills the documents into lines, separated by a space where they fit.
+pub fn fill(docs: Vec<Doc>) -> Doc {
+    let mut parts = Vec::new();
+
+    for (i, doc) in docs.into_iter().enumerate() {
+        if i > 0 {
+            parts.push(Doc::Line);
+        }
+        parts.push(doc);
+    }
+
+    Doc::Fill(parts)
+}
+
+/// Joins the documents with the separator between each of them.
+pub fn join(docs: Vec<Doc>, separator: Doc) -> Doc {
+    let mut parts = Vec::new();
+
+    for (i, doc) in docs.into_iter().enumerate() {
+        if i > 0 {
+            parts.push(separator.clone());
+        }
+        parts.push(doc);
+    }
+
+    Doc::Concat(parts)
+}
+
+impl Doc {
+    /// Whether the document always prints a line break, which breaks the
+    /// groups containing it.
+    pub fn has_hard_line(&self) -> bool {
+        match self {
+            Doc::Text(text) => text.contains('\n'),
+            Doc::HardLine => true,
+            Doc::Concat(docs) | Doc::Fill(docs) => {
+                docs.iter().any(Doc::has_hard_line)
+            }
+            Doc::Indent(doc) | Doc::Hang(doc) | Doc::Group(doc) => {
+                doc.has_hard_line()
+            }
+            Doc::Line | Doc::SoftLine | Doc::LineSuffix(_) | Doc::PadTo(_) => {
+                false
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+/// A document to print, or the rest of a fill.
+#[derive(Clone, Copy)]
+enum Part<'a> {
+    Doc(&'a Doc),
+    Fill(&'a [Doc]),
+}
+
+/// A part to print with the index of its indentation and its mode.
+type Command<'a> = (usize, Mode, Part<'a>);
+
+/// Renders the document, breaking the lines of groups and fills that don't
+/// fit in `width` columns and indenting each level with `indent_str`.
+pub fn render(doc: &Doc, width: usize, indent_str: &str) -> String {
+    let mut renderer = Renderer {
+        width,
+        indent_str,
+        indents: vec![String::new()],
+        output: String::new(),
+        column: 0,
+        pending_indent: None,
+        suffixes: Vec::new(),
+    };
+
+    renderer.print(doc);
+    renderer.output
+}
+
+struct Renderer<'a> {
+    width: usize,
+    indent_str: &'a str,
+    /// The indentation of each level of nesting that has been entered.
+    indents: Vec<String>,
+    output: String,
+    /// The number of characters on the current line.
+    column: usize,
+    /// The indentation of the current line, which is only printed once there
+    /// is text on the line so that blank lines stay empty.
+    pending_indent: Option<usize>,
+    suffixes: Vec<Command<'a>>,
+}
+
+impl<'a> Renderer<'a> {
+    fn print(&mut self, doc: &'a Doc) {
+        let mut commands: Vec<Command<'a>> =
+            vec![(0, Mode::Break, Part::Doc(doc))];
+
+        loop {
+            let Some((indent, mode, part)) = commands.pop() else {
+                // Suffixes at the end of the output still need printing.
+                match self.suffixes.is_empty() {
+                    true => break,
+                    false => {
+                        commands.extend(self.suffixes.drain(..).rev());
+                        continue;
+                    }
+                }
+            };
+
+            let doc = match part {
+                Part::Doc(doc) => doc,
+                Part::Fill(parts) => {
+                    self.print_fill(indent, mode, parts, &mut commands);
+                    continue;
+                }
+            };
+
+            match doc {
+                Doc::Text(text) => self.write(text),
+                Doc::Concat(docs) => commands.extend(
+                    docs.iter().rev().map(|doc| (indent, mode, Part::Doc(doc))),
+                ),
+                Doc::Line if mode == Mode::Flat => self.write(" "),
+                Doc::SoftLine if mode == Mode::Flat => {}
+                Doc::Line | Doc::SoftLine | Doc::HardLine => {
+                    // Line suffixes are printed before the line break.
+                    if !self.suffixes.is_empty() {
+                        commands.push((indent, mode, part));
+                        commands.extend(self.suffixes.drain(..).rev());
+                        continue;
+                    }
+
+                    self.output.push('\n');
+                    self.column = 0;
+                    self.pending_indent = Some(indent);
+                }
+                Doc::Indent(doc) => {
+                    let nested =
+                        format!("{}{}", self.indents[indent], self.indent_str);
+                    self.indents.push(nested);
+                    commands.push((
+                        self.indents.len() - 1,
+                        mode,
+                        Part::Doc(doc),
+                    ));
+                }
+                Doc::Hang(doc) => {
+                    let nested = self.hang_indent();
+                    self.indents.push(nested);
+                    commands.push((
+                        self.indents.len() - 1,
+                        mode,
+                        Part::Doc(doc),
+                    ));
+                }
+                Doc::Group(doc) => {
+                    let fits = !doc.has_hard_line()
+                        && (mode == Mode::Flat
+                            || self.fits(
+                                vec![(Mode::Flat, Part::Doc(doc))],
+                                &commands,
+                                false,
+                            ));
+                    let mode = if fits { Mode::Flat } else { Mode::Break };
+                    commands.push((indent, mode, Part::Doc(doc)));
+                }
+                Doc::Fill(parts) => {
+                    self.print_fill(indent, mode, parts, &mut commands)
+                }
+                Doc::LineSuffix(doc) => {
+                    self.suffixes.push((indent, mode, Part::Doc(doc)));
+                }
+                Doc::PadTo(column) => {
+                    self.write("");
+                    while self.column < *column {
+                        self.output.push(' ');
+                        self.column += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Prints the first content of a fill, followed by the line after it as a
+    /// space if the next content fits on the rest of the line.
+    fn print_fill(
+        &mut self,
+        indent: usize,
+        mode: Mode,
+        parts: &'a [Doc],
+        commands: &mut Vec<Command<'a>>,
+    ) {
+        let [content, rest @ ..] = parts else {
+            return;
+        };
+
+        let content_fits =
+            self.fits(vec![(Mode::Flat, Part::Doc(content))], &[], true);
+        let content_mode = if content_fits { Mode::Flat } else { Mode::Break };
+
+        let [separator, next, ..] = rest else {
+            // A trailing separator is printed like its content.
+            for doc in rest.iter().chain([content]) {
+                commands.push((indent, content_mode, Part::Doc(doc)));
+            }
+            return;
+        };
+
+        let both_fit = self.fits(
+            vec![
+                (Mode::Flat, Part::Doc(next)),
+                (Mode::Flat, Part::Doc(separator)),
+                (Mode::Flat, Part::Doc(content)),
+            ],
+            &[],
+            true,
+        );
+        let separator_mode = if both_fit { Mode::Flat } else { Mode::Break };
+
+        commands.push((indent, mode, Part::Fill(&rest[1..])));
+        commands.push((indent, separator_mode, Part::Doc(separator)));
+        commands.push((indent, content_mode, Part::Doc(content)));
+    }
+
+    /// Whether the parts fit on the rest of the line, followed by the rest of
+    /// the commands up to their first line break. Both are stacks, so the
+    /// part to print first is last.
+    fn fits(
+        &self,
+        mut parts: Vec<(Mode, Part<'a>)>,
+        rest: &[Command<'a>],
+        must_be_flat: bool,
+    ) -> bool {
+        let mut remaining = self.width.saturating_sub(self.column);
+        let mut rest = rest.iter().rev();
+
+        loop {
+            let (mode, part) = match parts.pop() {
+                Some(part) => part,
+                None => match rest.next() {
+                    Some(&(_, mode, part)) => (mode, part),
+                    None => return true,
+                },
+            };
+
+            let doc = match part {
+                Part::Doc(doc) => doc,
+                Part::Fill(docs) => {
+                    parts.extend(
+                        docs.iter().rev().map(|d| (mode, Part::Doc(d))),
+                    );
+                    continue;
+                }
+            };
+
+            match doc {
+                Doc::Text(text) => {
+                    let line = text.split('\n').next().unwrap_or_default();
+                    match remaining.checked_sub(line.chars().count()) {
+                        Some(left) => remaining = left,
+                        None => return false,
+                    }
+
+                    if text.contains('\n') {
+                        return true;
+                    }
+                }
+                Doc::Concat(docs) | Doc::Fill(docs) => parts
+                    .extend(docs.iter().rev().map(|d| (mode, Part::Doc(d)))),
+                Doc::Indent(doc) | Doc::Hang(doc) => {
+                    parts.push((mode, Part::Doc(doc)))
+                }
+                Doc::Group(doc) => {
+                    let broken = doc.has_hard_line();
+                    if must_be_flat && broken {
+                        return false;
+                    }
+
+                    let mode = if broken { Mode::Break } else { mode };
+                    parts.push((mode, Part::Doc(doc)));
+                }
+                Doc::Line if mode == Mode::Flat => {
+                    match remaining.checked_sub(1) {
+                        Some(left) => remaining = left,
+                        None => return false,
+                    }
+                }
+                Doc::SoftLine if mode == Mode::Flat => {}
+                Doc::Line | Doc::SoftLine | Doc::HardLine => return true,
+                Doc::LineSuffix(_) | Doc::PadTo(_) => {}
+            }
+        }
+    }
+
+    /// Writes text on the current line, after its indentation.
+    fn write(&mut self, text: &str) {
+        if let Some(indent) = self.pending_indent.take() {
+            self.output.push_str(&self.indents[indent]);
+            self.column = self.indents[indent].chars().count();
+        }
+
+        self.output.push_str(text);
+        self.column = match text.rfind('\n') {
+            Some(i) => text[i + 1..].chars().count(),
+            None => self.column + text.chars().count(),
+        };
+    }
+
+    /// The indentation up to the current column, which keeps the whitespace
+    /// at the start of the line so that tabs are kept.
+    fn hang_indent(&self) -> String {
+        if let Some(indent) = self.pending_indent {
+            return self.indents[indent].clone();
+        }
+
+        let start = self.output.rfind('\n').map_or(0, |i| i + 1);
+        let line = &self.output[start..];
+        let text = line.trim_start();
+
+        format!(
+            "{}{}",
+            &line[..line.len() - text.len()],
+            " ".repeat(text.chars().count())
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_indents_lines() {
+        let doc = concat(vec![
+            text("node {"),
+            indent(concat(vec![
+                Doc::HardLine,
+                text("a;"),
+                Doc::HardLine,
+                Doc::HardLine,
+                text("b;"),
+            ])),
+            Doc::HardLine,
+            text("};"),
+        ]);
+
+        assert_eq!(render(&doc, 80, "  "), "node {\n  a;\n\n  b;\n};");
+    }
+
+    #[test]
+    fn it_breaks_groups_that_dont_fit() {
+        let doc = group(concat(vec![
+            text("<"),
+            indent(concat(vec![Doc::SoftLine, text("1 2 3")])),
+            Doc::SoftLine,
+            text(">"),
+        ]));
+
+        assert_eq!(render(&doc, 80, "  "), "<1 2 3>");
+        assert_eq!(render(&doc, 4, "  "), "<\n  1 2 3\n>");
+    }
+
+    #[test]
+    fn it_fills_lines() {
+        let cells = ["0x1", "0x2", "0x3", "0x4>;"].map(text).to_vec();
+        let doc = concat(vec![text("reg = <"), hang(fill(cells))]);
+
+        assert_eq!(render(&doc, 80, "  "), "reg = <0x1 0x2 0x3 0x4>;");
+        assert_eq!(render(&doc, 16, "  "), "reg = <0x1 0x2\n       0x3 0x4>;");
+    }
+
+    #[test]
+    fn it_prints_line_suffixes_before_line_breaks() {
+        let doc = concat(vec![
+            text("a;"),
+            line_suffix(concat(vec![Doc::PadTo(6), text("// a")])),
+            Doc::HardLine,
+            text("bb;"),
+            line_suffix(concat(vec![text(" "), text("// b")])),
+        ]);
+
+        assert_eq!(render(&doc, 80, "  "), "a;    // a\nbb; // b");
+    }
+
+    #[test]
+    fn it_keeps_the_lines_of_text_verbatim() {
+        let doc = indent(concat(vec![
+            Doc::HardLine,
+            text("#define A \\\n    1"),
+            Doc::HardLine,
+            hang(concat(vec![text("/*"), Doc::HardLine, text(" */")])),
+        ]));
+
+        assert_eq!(
+            render(&doc, 80, "\t"),
+            "\n\t#define A \\\n    1\n\t/*\n\t */"
+        );
+    }
+}
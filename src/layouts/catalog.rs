@@ -0,0 +1,76 @@
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use super::custom::from_grid;
+use super::detect::DetectionRule;
+use super::KeyboardLayout;
+
+/// A layout bundled with dtsfmt.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuiltinLayout {
+    pub description: String,
+    /// Other names the layout can be referred to by.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Which files the layout is picked for by `layout = "auto"`.
+    #[serde(default)]
+    pub detect: DetectionRule,
+    grid: Vec<Vec<usize>>,
+    #[serde(default)]
+    gutters: Vec<usize>,
+}
+
+impl BuiltinLayout {
+    pub fn to_layout(&self) -> KeyboardLayout {
+        from_grid(&self.grid, &self.gutters)
+            .expect("Bundled layouts should be valid")
+    }
+}
+
+/// Returns the bundled layouts by name, which are embedded in the binary.
+pub fn catalog() -> &'static BTreeMap<String, BuiltinLayout> {
+    static CATALOG: OnceLock<BTreeMap<String, BuiltinLayout>> = OnceLock::new();
+
+    CATALOG.get_or_init(|| {
+        toml::from_str(include_str!("catalog.toml"))
+            .expect("Failed to parse the layout catalog")
+    })
+}
+
+/// Finds the bundled layout with the given name or alias.
+pub fn find(name: &str) -> Option<&'static BuiltinLayout> {
+    let catalog = catalog();
+
+    catalog.get(name).or_else(|| {
+        catalog
+            .values()
+            .find(|layout| layout.aliases.iter().any(|alias| alias == name))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_loads_the_catalog() {
+        for (name, layout) in catalog() {
+            let result = from_grid(&layout.grid, &layout.gutters);
+            assert!(result.is_ok(), "invalid layout {}", name);
+        }
+
+        let key_count = |name| find(name).unwrap().to_layout().key_count();
+        assert_eq!(key_count("kinesis:adv360"), 76);
+        assert_eq!(key_count("moergo:glove80"), 80);
+        assert_eq!(key_count("sweep"), 34);
+        assert_eq!(key_count("ferris"), 34);
+        assert_eq!(key_count("corne"), 42);
+        assert_eq!(key_count("lily58"), 58);
+        assert_eq!(key_count("kyria"), 50);
+        assert_eq!(key_count("iris"), 56);
+        assert!(find("unknown").is_none());
+    }
+}
@@ -0,0 +1,255 @@
+use std::path::{Path, PathBuf};
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+
+use super::import::{load_layout, LayoutFileFormat};
+use super::{KeyPosition, KeyboardLayout};
+
+/// A row of keys described by the number of keys it contains and how many
+/// columns it is shifted to the right.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomRow {
+    pub keys: usize,
+    #[serde(default)]
+    pub offset: usize,
+}
+
+/// The keys that select the kind of a custom layout.
+const LAYOUT_KINDS: [&str; 5] = ["grid", "rows", "keys", "bindings", "file"];
+
+/// A keyboard layout defined in the config file rather than built in to
+/// dtsfmt. The kind of layout is picked by which of its keys is present, so
+/// that a mistake in its fields is reported for that kind of layout.
+#[derive(Clone, Deserialize)]
+#[serde(remote = "Self", rename_all = "lowercase", deny_unknown_fields)]
+pub enum CustomLayout {
+    /// A grid of rows where `1` is a key and `0` is an empty space.
+    Grid {
+        grid: Vec<Vec<usize>>,
+        #[serde(default)]
+        gutters: Vec<usize>,
+    },
+    /// A list of rows described by their key count and column offset.
+    Rows {
+        rows: Vec<CustomRow>,
+        #[serde(default)]
+        gutters: Vec<usize>,
+    },
+    /// The `[x, y]` cell of each key in the order of the bindings, where `x`
+    /// is the column and `y` is the row.
+    Keys {
+        keys: Vec<[usize; 2]>,
+        #[serde(default)]
+        gutters: Vec<usize>,
+    },
+    /// A flat grid of `row_count` rows where `1` is a key and `0` is an empty
+    /// space.
+    Bindings { bindings: Vec<usize>, row_count: usize },
+    /// A layout imported from a QMK or KLE file.
+    File {
+        file: PathBuf,
+        format: LayoutFileFormat,
+        #[serde(default)]
+        name: Option<String>,
+    },
+}
+
+impl<'de> Deserialize<'de> for CustomLayout {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let table = toml::Table::deserialize(deserializer)?;
+
+        let kinds = LAYOUT_KINDS
+            .into_iter()
+            .filter(|kind| table.contains_key(*kind))
+            .collect::<Vec<_>>();
+
+        let kind = match kinds.as_slice() {
+            [kind] => kind.to_string(),
+            [] => {
+                let keys = table.keys().map(|key| format!("`{}`", key));
+                return Err(D::Error::custom(format!(
+                    "expected a layout with one of `{}`, found {}",
+                    LAYOUT_KINDS.join("`, `"),
+                    keys.collect::<Vec<_>>().join(", ")
+                )));
+            }
+            _ => {
+                return Err(D::Error::custom(format!(
+                    "a layout can only have one of `{}`",
+                    kinds.join("`, `")
+                )))
+            }
+        };
+
+        let tagged =
+            toml::Table::from_iter([(kind, toml::Value::Table(table))]);
+        CustomLayout::deserialize(toml::Value::Table(tagged))
+            .map_err(|err| D::Error::custom(err.message()))
+    }
+}
+
+impl CustomLayout {
+    /// Validates the layout and converts it to a `KeyboardLayout`.
+    pub fn to_layout(&self) -> Result<KeyboardLayout, String> {
+        match self {
+            CustomLayout::Grid { grid, gutters } => from_grid(grid, gutters),
+            CustomLayout::Rows { rows, gutters } => from_rows(rows, gutters),
+            CustomLayout::Keys { keys, gutters } => {
+                let keys = keys.iter().map(|&[x, y]| KeyPosition { x, y });
+                KeyboardLayout::new(keys.collect(), gutters.clone())
+            }
+            CustomLayout::Bindings { bindings, row_count } => {
+                from_bindings(bindings, *row_count)
+            }
+            CustomLayout::File { file, format, name } => {
+                load_layout(file, *format, name.as_deref())
+            }
+        }
+    }
+
+    /// Resolves relative file paths against the given directory, which is the
+    /// directory containing the config file.
+    pub fn resolve_paths(&mut self, dir: &Path) {
+        if let CustomLayout::File { file, .. } = self {
+            *file = dir.join(&file);
+        }
+    }
+}
+
+fn validate_keys(bindings: &[usize]) -> Result<(), String> {
+    match bindings.iter().find(|&&is_key| is_key > 1) {
+        Some(value) => Err(format!(
+            "found `{}` but keys must be either 1 (key) or 0 (empty space)",
+            value
+        )),
+        None => Ok(()),
+    }
+}
+
+pub(super) fn from_grid(
+    grid: &[Vec<usize>],
+    gutters: &[usize],
+) -> Result<KeyboardLayout, String> {
+    let row_size = match grid.first() {
+        Some(row) if !row.is_empty() => row.len(),
+        _ => return Err("the grid must have at least one key".to_owned()),
+    };
+
+    for (i, row) in grid.iter().enumerate() {
+        if row.len() != row_size {
+            return Err(format!(
+                "row {} has {} columns but row 1 has {}, all rows must be the \
+                 same length",
+                i + 1,
+                row.len(),
+                row_size
+            ));
+        }
+    }
+
+    validate_keys(&grid.concat())?;
+
+    let layout = KeyboardLayout::from_grid(grid);
+    KeyboardLayout::new(layout.keys, gutters.to_vec())
+}
+
+fn from_rows(
+    rows: &[CustomRow],
+    gutters: &[usize],
+) -> Result<KeyboardLayout, String> {
+    let row_size =
+        rows.iter().map(|row| row.offset + row.keys).max().unwrap_or(0);
+
+    if row_size == 0 {
+        return Err("the rows must have at least one key".to_owned());
+    }
+
+    let keys = rows
+        .iter()
+        .enumerate()
+        .flat_map(|(y, row)| {
+            (row.offset..row.offset + row.keys)
+                .map(move |x| KeyPosition { x, y })
+        })
+        .collect();
+
+    KeyboardLayout::new(keys, gutters.to_vec())
+}
+
+fn from_bindings(
+    bindings: &[usize],
+    row_count: usize,
+) -> Result<KeyboardLayout, String> {
+    if row_count == 0 || !bindings.len().is_multiple_of(row_count) {
+        return Err(format!(
+            "row_count {} doesn't evenly divide the {} bindings",
+            row_count,
+            bindings.len()
+        ));
+    }
+
+    validate_keys(bindings)?;
+
+    let row_size = bindings.len() / row_count;
+    let grid = bindings.chunks(row_size).map(<[usize]>::to_vec);
+
+    Ok(KeyboardLayout::from_grid(&grid.collect::<Vec<_>>()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<KeyboardLayout, String> {
+        toml::from_str::<CustomLayout>(text).unwrap().to_layout()
+    }
+
+    #[test]
+    fn it_converts_grids() {
+        let layout = parse("grid = [[1, 0, 1], [0, 1, 0]]").unwrap();
+        assert_eq!(layout.grid(), vec![vec![1, 0, 1], vec![0, 1, 0]]);
+
+        let layout = parse("grid = [[1, 1, 1, 1]]\ngutters = [1]").unwrap();
+        assert_eq!(layout.grid(), vec![vec![1, 1, 0, 1, 1]]);
+    }
+
+    #[test]
+    fn it_converts_rows() {
+        let layout =
+            parse("rows = [{ keys = 4 }, { keys = 2, offset = 1 }]").unwrap();
+
+        assert_eq!(layout.grid(), vec![vec![1, 1, 1, 1], vec![0, 1, 1, 0]]);
+    }
+
+    #[test]
+    fn it_converts_keys() {
+        let layout = parse("keys = [[0, 0], [2, 0], [1, 1]]").unwrap();
+
+        assert_eq!(layout.grid(), vec![vec![1, 0, 1], vec![0, 1, 0]]);
+    }
+
+    #[test]
+    fn it_rejects_invalid_layouts() {
+        assert!(parse("grid = [[1, 1, 1], [1, 1]]").is_err());
+        assert!(parse("grid = [[1, 2]]").is_err());
+        assert!(parse("bindings = [1, 1, 1]\nrow_count = 2").is_err());
+        assert!(parse("keys = [[0, 0], [0, 0]]").is_err());
+    }
+
+    #[test]
+    fn it_reports_invalid_fields() {
+        let error = |text| match toml::from_str::<CustomLayout>(text) {
+            Ok(_) => String::new(),
+            Err(err) => err.message().to_owned(),
+        };
+
+        assert!(error("gird = [[1, 1]]").contains("found `gird`"));
+        assert!(error("grid = [[1]]\ngutter = [0]").contains("`gutter`"));
+        assert!(error("bindings = [1]").contains("missing field `row_count`"));
+        assert!(error("grid = [[1]]\nrows = []").contains("only have one"));
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::path::Path;
+
+use serde::Deserialize;
+use tree_sitter::Node;
+
+use super::custom::CustomLayout;
+use super::document::Document;
+use super::{catalog, physical_layout, resolve_named};
+
+/// What a file must match for a layout to be picked by `layout = "auto"`.
+#[derive(Clone, Default, Deserialize)]
+pub struct DetectionRule {
+    /// Keymap file names without the extension, e.g. `corne` matches
+    /// `corne.keymap` and `corne_v4.keymap`.
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// Names of files included by the keymap, e.g. `glove80.dtsi`.
+    #[serde(default)]
+    pub includes: Vec<String>,
+    /// Values of a `compatible` property in the keymap.
+    #[serde(default)]
+    pub compatibles: Vec<String>,
+}
+
+/// An `[[auto_layouts]]` entry of the config file, which picks a bundled or
+/// custom layout for the files matching its rule.
+#[derive(Clone, Deserialize)]
+pub struct AutoLayout {
+    pub layout: String,
+    #[serde(flatten)]
+    pub rule: DetectionRule,
+}
+
+/// The layout picked for a file and why it was picked.
+#[derive(Debug)]
+pub struct Detection {
+    pub layout: String,
+    pub reason: String,
+}
+
+impl Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` ({})", self.layout, self.reason)
+    }
+}
+
+/// Picks a layout for the document from the `[[auto_layouts]]` of the config
+/// file, the document's physical layout and then the bundled layouts. For each
+/// set of rules, the file name is checked first, then the included files and
+/// the `compatible` strings in the file. As a last resort, a layout is picked
+/// if it is the only one with as many keys as the layers have bindings.
+pub fn detect_layout(
+    auto_layouts: &[AutoLayout],
+    custom_layouts: &BTreeMap<String, CustomLayout>,
+    document: &Document,
+) -> Option<Detection> {
+    let configured = auto_layouts
+        .iter()
+        .map(|auto| (auto.layout.as_str(), &auto.rule))
+        .collect::<Vec<_>>();
+    let bundled = catalog()
+        .iter()
+        .map(|(name, layout)| (name.as_str(), &layout.detect))
+        .collect::<Vec<_>>();
+
+    detect_by_rules(&configured, document)
+        .or_else(|| detect_physical_layout(document))
+        .or_else(|| detect_by_rules(&bundled, document))
+        .or_else(|| detect_by_key_count(custom_layouts, document))
+}
+
+fn detect_by_rules(
+    rules: &[(&str, &DetectionRule)],
+    document: &Document,
+) -> Option<Detection> {
+    let stem = document
+        .path
+        .as_deref()
+        .and_then(Path::file_stem)
+        .and_then(|stem| stem.to_str());
+    let includes = document
+        .include_names()
+        .into_iter()
+        .filter_map(|include| {
+            let name = Path::new(&include).file_name()?.to_str()?;
+            Some(name.to_owned())
+        })
+        .collect::<Vec<_>>();
+
+    let find = |describe: &dyn Fn(&DetectionRule) -> Option<String>| {
+        rules.iter().find_map(|(layout, rule)| {
+            describe(rule)
+                .map(|reason| Detection { layout: layout.to_string(), reason })
+        })
+    };
+
+    find(&|rule| {
+        let stem = stem?;
+        rule.files
+            .iter()
+            .find(|file| matches_stem(stem, file))
+            .map(|file| format!("the file name matches `{}`", file))
+    })
+    .or_else(|| {
+        find(&|rule| {
+            rule.includes
+                .iter()
+                .find(|include| includes.contains(include))
+                .map(|include| format!("the file includes `{}`", include))
+        })
+    })
+    .or_else(|| {
+        find(&|rule| {
+            rule.compatibles
+                .iter()
+                .find(|compatible| {
+                    !document.find_compatible(compatible).is_empty()
+                })
+                .map(|compatible| format!("found `{}`", compatible))
+        })
+    })
+}
+
+/// The keys of a physical layout are the most accurate description of the
+/// keyboard, so they are preferred over the hard-coded bundled layouts.
+fn detect_physical_layout(document: &Document) -> Option<Detection> {
+    physical_layout::get_layout(document).ok()?;
+
+    Some(Detection {
+        layout: "auto:physical-layout".to_owned(),
+        reason: format!("found `{}` keys", physical_layout::COMPATIBLE),
+    })
+}
+
+/// A file name matches if it is the same or starts with the name followed by
+/// a `_` or `-`, which covers revisions and variants of a keyboard.
+fn matches_stem(stem: &str, name: &str) -> bool {
+    stem.strip_prefix(name).is_some_and(|rest| {
+        rest.is_empty() || rest.starts_with('_') || rest.starts_with('-')
+    })
+}
+
+fn detect_by_key_count(
+    custom_layouts: &BTreeMap<String, CustomLayout>,
+    document: &Document,
+) -> Option<Detection> {
+    let counts = binding_counts(document);
+    let count = *counts.first()?;
+    if counts.iter().any(|&other| other != count) {
+        return None;
+    }
+
+    let names = custom_layouts.keys().chain(catalog().keys());
+    let mut matches = names.filter(|name| {
+        resolve_named(name, custom_layouts)
+            .is_ok_and(|layout| layout.key_count() == count)
+    });
+
+    let layout = matches.next()?;
+    if matches.next().is_some() {
+        return None;
+    }
+
+    Some(Detection {
+        layout: layout.clone(),
+        reason: format!("it is the only layout with {} keys", count),
+    })
+}
+
+/// Counts the bindings of each layer in the keymap.
+fn binding_counts(document: &Document) -> Vec<usize> {
+    document
+        .find_compatible("zmk,keymap")
+        .iter()
+        .flat_map(|keymap| {
+            let mut cursor = keymap.walk();
+            keymap
+                .children(&mut cursor)
+                .filter(|child| child.kind() == "node")
+                .collect::<Vec<Node>>()
+        })
+        .filter_map(|layer| document.property(&layer, "bindings"))
+        .map(|bindings| {
+            bindings
+                .split(|c: char| c.is_whitespace() || c == '<' || c == '>')
+                .filter(|word| word.starts_with('&'))
+                .count()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_matches_file_names() {
+        assert!(matches_stem("corne", "corne"));
+        assert!(matches_stem("kyria_rev3", "kyria"));
+        assert!(matches_stem("iris-lm", "iris"));
+        assert!(!matches_stem("cornea", "corne"));
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashSet;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use tree_sitter::{Node, Tree};
+
+use crate::parser::parse;
+
+/// A parsed devicetree file that layouts can be derived from.
+pub struct Document {
+    pub tree: Tree,
+    pub source: String,
+    pub path: Option<PathBuf>,
+}
+
+impl Document {
+    pub fn new(source: &str, path: Option<&Path>) -> Self {
+        Self {
+            tree: parse(source.to_owned()),
+            source: source.to_owned(),
+            path: path.map(Path::to_path_buf),
+        }
+    }
+
+    fn load(path: &Path) -> Option<Self> {
+        let source = fs::read_to_string(path).ok()?;
+        Some(Self::new(&source, Some(path)))
+    }
+
+    pub fn text(&self, node: &Node) -> &str {
+        node.utf8_text(self.source.as_bytes()).unwrap_or("").trim()
+    }
+
+    /// Finds all nodes in the document, including nested nodes.
+    pub fn nodes(&self) -> Vec<Node<'_>> {
+        let mut nodes = Vec::new();
+        collect_nodes(self.tree.root_node(), &mut nodes);
+        nodes
+    }
+
+    /// Finds all nodes whose `compatible` property contains the given value.
+    pub fn find_compatible(&self, compatible: &str) -> Vec<Node<'_>> {
+        let quoted = format!("\"{}\"", compatible);
+
+        self.nodes()
+            .into_iter()
+            .filter(|node| {
+                self.property(node, "compatible")
+                    .is_some_and(|value| value.contains(&quoted))
+            })
+            .collect()
+    }
+
+    /// Returns the raw value of the given property of a node, without the `=`
+    /// and `;`.
+    pub fn property(&self, node: &Node, name: &str) -> Option<&str> {
+        let mut cursor = node.walk();
+        let property = node
+            .children(&mut cursor)
+            .filter(|child| child.kind() == "property")
+            .find(|child| {
+                child.child(0).is_some_and(|n| self.text(&n) == name)
+            })?;
+
+        let (_, value) = self.text(&property).split_once('=')?;
+        Some(value.trim().trim_end_matches(';').trim_end())
+    }
+
+    /// Returns the name of a node, e.g. `default_layer` for
+    /// `base: default_layer { ... };`.
+    pub fn node_name(&self, node: &Node) -> &str {
+        let header = self.header(node);
+        header.rsplit(':').next().unwrap_or(header).trim()
+    }
+
+    /// Returns the labels of a node, e.g. `base` for
+    /// `base: default_layer { ... };`.
+    pub fn node_labels(&self, node: &Node) -> Vec<&str> {
+        let header = self.header(node);
+        let mut parts = header.split(':').map(str::trim).collect::<Vec<_>>();
+        parts.pop();
+        parts
+    }
+
+    fn header(&self, node: &Node) -> &str {
+        let text = self.text(node);
+        text.split('{').next().unwrap_or(text).trim()
+    }
+
+    /// Reads a property of the node with the given compatible that the keymap
+    /// uses, searching this document and the files it includes. Boards with
+    /// several such nodes select one in the `chosen` node, otherwise the first
+    /// one is used.
+    pub fn chosen_property(
+        &self,
+        compatible: &str,
+        property: &str,
+    ) -> Option<String> {
+        let includes = self.load_includes();
+        let documents =
+            iter::once(self).chain(includes.iter()).collect::<Vec<_>>();
+
+        let chosen = documents.iter().find_map(|doc| {
+            doc.nodes()
+                .iter()
+                .filter(|node| doc.node_name(node) == "chosen")
+                .find_map(|node| doc.property(node, compatible))
+                .map(|value| value.trim_start_matches('&').to_owned())
+        });
+
+        let mut candidates = Vec::new();
+        for doc in &documents {
+            for node in doc.find_compatible(compatible) {
+                if let Some(value) = doc.property(&node, property) {
+                    let is_chosen = chosen.as_ref().is_some_and(|label| {
+                        doc.node_labels(&node).contains(&label.as_str())
+                    });
+
+                    candidates.push((is_chosen, value.to_owned()));
+                }
+            }
+        }
+
+        let index = candidates
+            .iter()
+            .position(|(is_chosen, _)| *is_chosen)
+            .unwrap_or(0);
+
+        candidates.into_iter().nth(index).map(|(_, value)| value)
+    }
+
+    /// Returns the paths of the files included by this document as written,
+    /// e.g. `dt-bindings/zmk/keys.h`.
+    pub fn include_names(&self) -> Vec<String> {
+        let mut includes = Vec::new();
+        collect_includes(self.tree.root_node(), &self.source, &mut includes);
+        includes
+    }
+
+    /// Returns the paths of the files included by this document that exist
+    /// on disk. Includes are resolved relative to the document's directory.
+    fn includes(&self) -> Vec<PathBuf> {
+        let Some(dir) = self.path.as_deref().and_then(Path::parent) else {
+            return Vec::new();
+        };
+
+        self.include_names()
+            .into_iter()
+            .map(|include| dir.join(include))
+            .filter(|path| path.is_file())
+            .collect()
+    }
+
+    /// Loads every file included by this document, recursively.
+    pub fn load_includes(&self) -> Vec<Document> {
+        let mut visited = HashSet::new();
+        let mut documents = Vec::new();
+        let mut pending = self.includes();
+
+        while let Some(path) = pending.pop() {
+            if !visited.insert(path.clone()) {
+                continue;
+            }
+
+            if let Some(document) = Document::load(&path) {
+                pending.extend(document.includes());
+                documents.push(document);
+            }
+        }
+
+        documents
+    }
+}
+
+fn collect_nodes<'a>(node: Node<'a>, nodes: &mut Vec<Node<'a>>) {
+    let mut cursor = node.walk();
+
+    for child in node.children(&mut cursor) {
+        if child.kind() == "node" {
+            nodes.push(child);
+        }
+
+        collect_nodes(child, nodes);
+    }
+}
+
+fn collect_includes(node: Node, source: &str, includes: &mut Vec<String>) {
+    let mut cursor = node.walk();
+
+    for child in node.children(&mut cursor) {
+        if child.kind() == "preproc_include" {
+            let path = child
+                .child(1)
+                .and_then(|n| n.utf8_text(source.as_bytes()).ok())
+                .unwrap_or("")
+                .trim()
+                .trim_matches(|c| c == '"' || c == '<' || c == '>');
+
+            includes.push(path.to_owned());
+        } else {
+            collect_includes(child, source, includes);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::physical_layout::{self, PhysicalKey};
+use super::KeyboardLayout;
+
+/// The formats of layout files that can be imported.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayoutFileFormat {
+    /// The `layouts` of a QMK `info.json` or `keyboard.json` file.
+    Qmk,
+    /// A raw data export from keyboard-layout-editor.com.
+    Kle,
+}
+
+#[derive(Deserialize)]
+struct QmkInfo {
+    layouts: BTreeMap<String, QmkLayout>,
+}
+
+#[derive(Deserialize)]
+struct QmkLayout {
+    layout: Vec<QmkKey>,
+}
+
+#[derive(Deserialize)]
+struct QmkKey {
+    x: f64,
+    y: f64,
+    #[serde(default = "default_key_size")]
+    w: f64,
+    #[serde(default = "default_key_size")]
+    h: f64,
+    #[serde(default)]
+    r: f64,
+    #[serde(default)]
+    rx: f64,
+    #[serde(default)]
+    ry: f64,
+}
+
+fn default_key_size() -> f64 {
+    1.0
+}
+
+/// Both QMK and KLE use key units and degrees, which are converted to the
+/// centi-keyunits and centi-degrees used by ZMK physical layouts.
+fn physical_key(
+    w: f64,
+    h: f64,
+    x: f64,
+    y: f64,
+    r: f64,
+    rx: f64,
+    ry: f64,
+) -> PhysicalKey {
+    PhysicalKey {
+        w: w * 100.0,
+        h: h * 100.0,
+        x: x * 100.0,
+        y: y * 100.0,
+        r: r * 100.0,
+        rx: rx * 100.0,
+        ry: ry * 100.0,
+    }
+}
+
+/// Imports a layout from a QMK or KLE file. The name selects one of the
+/// layouts in a QMK file and may be omitted if there is only one.
+pub fn load_layout(
+    path: &Path,
+    format: LayoutFileFormat,
+    name: Option<&str>,
+) -> Result<KeyboardLayout, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+
+    let keys = match format {
+        LayoutFileFormat::Qmk => parse_qmk(&text, name),
+        LayoutFileFormat::Kle => parse_kle(&text),
+    }
+    .map_err(|err| format!("{}: {}", path.display(), err))?;
+
+    if keys.is_empty() {
+        return Err(format!("{}: the layout has no keys", path.display()));
+    }
+
+    Ok(physical_layout::from_keys(&keys))
+}
+
+fn parse_qmk(
+    text: &str,
+    name: Option<&str>,
+) -> Result<Vec<PhysicalKey>, String> {
+    let info: QmkInfo =
+        serde_json::from_str(text).map_err(|err| err.to_string())?;
+
+    let names = || info.layouts.keys().cloned().collect::<Vec<_>>().join(", ");
+    let layout = match name {
+        Some(name) => info.layouts.get(name).ok_or_else(|| {
+            format!("no layout named `{}`, expected one of: {}", name, names())
+        })?,
+        None if info.layouts.len() == 1 => {
+            info.layouts.values().next().unwrap()
+        }
+        None => {
+            return Err(format!(
+                "the file has multiple layouts, select one with `name`: {}",
+                names()
+            ))
+        }
+    };
+
+    Ok(layout
+        .layout
+        .iter()
+        .map(|key| {
+            physical_key(key.w, key.h, key.x, key.y, key.r, key.rx, key.ry)
+        })
+        .collect())
+}
+
+/// Parses the raw data of a KLE layout, which is a list of rows containing
+/// key legends and objects that change the position and size of the keys that
+/// follow them.
+fn parse_kle(text: &str) -> Result<Vec<PhysicalKey>, String> {
+    let json: Value =
+        serde_json::from_str(text).map_err(|err| err.to_string())?;
+    let rows = json.as_array().ok_or("expected a list of rows")?;
+
+    let mut keys = Vec::new();
+    let (mut x, mut y) = (0.0, 0.0);
+    let (mut r, mut rx, mut ry) = (0.0, 0.0, 0.0);
+
+    for row in rows {
+        // The first entry may be an object with metadata about the keyboard.
+        let Some(items) = row.as_array() else {
+            continue;
+        };
+
+        let (mut w, mut h) = (1.0, 1.0);
+        let mut decal = false;
+
+        for item in items {
+            match item {
+                Value::Object(props) => {
+                    let prop = |name| props.get(name).and_then(Value::as_f64);
+
+                    if let Some(value) = prop("r") {
+                        r = value;
+                    }
+
+                    // Changing the rotation origin starts a new cluster of keys
+                    // positioned relative to the origin.
+                    if let Some(value) = prop("rx") {
+                        rx = value;
+                        (x, y) = (rx, ry);
+                    }
+                    if let Some(value) = prop("ry") {
+                        ry = value;
+                        (x, y) = (rx, ry);
+                    }
+
+                    x += prop("x").unwrap_or(0.0);
+                    y += prop("y").unwrap_or(0.0);
+                    w = prop("w").unwrap_or(w);
+                    h = prop("h").unwrap_or(h);
+                    decal = props
+                        .get("d")
+                        .and_then(Value::as_bool)
+                        .unwrap_or(decal);
+                }
+                Value::String(_) => {
+                    if !decal {
+                        keys.push(physical_key(w, h, x, y, r, rx, ry));
+                    }
+
+                    x += w;
+                    (w, h) = (1.0, 1.0);
+                    decal = false;
+                }
+                _ => return Err(format!("unexpected `{}` in a row", item)),
+            }
+        }
+
+        x = rx;
+        y += 1.0;
+    }
+
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_qmk_layouts() {
+        let text = r#"{
+          "layouts": {
+            "LAYOUT": {
+              "layout": [
+                { "matrix": [0, 0], "x": 0, "y": 0 },
+                { "matrix": [0, 1], "x": 1, "y": 0, "w": 1.5 },
+                { "matrix": [1, 0], "x": 0.5, "y": 1, "r": 15, "rx": 0, "ry": 1 }
+              ]
+            }
+          }
+        }"#;
+
+        let keys = parse_qmk(text, None).unwrap();
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys[1].w, 150.0);
+        assert_eq!(keys[2].r, 1500.0);
+
+        assert!(parse_qmk(text, Some("LAYOUT_other")).is_err());
+    }
+
+    #[test]
+    fn it_parses_kle_layouts() {
+        let text = r#"[
+          { "name": "test" },
+          ["Q", "W", { "x": 1, "w": 2 }, "E"],
+          [{ "d": true }, "decal", "A", "S"]
+        ]"#;
+
+        let keys = parse_kle(text).unwrap();
+        let positions =
+            keys.iter().map(|k| (k.x, k.y, k.w)).collect::<Vec<_>>();
+
+        assert_eq!(
+            positions,
+            vec![
+                (0.0, 0.0, 100.0),
+                (100.0, 0.0, 100.0),
+                (300.0, 0.0, 200.0),
+                (100.0, 100.0, 100.0),
+                (200.0, 100.0, 100.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+
+/// The cell of a key in the bindings table, where `x` is the column and `y`
+/// is the row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct KeyPosition {
+    pub x: usize,
+    pub y: usize,
+}
+
+/// The position of each key of a keyboard, which determines where the
+/// bindings of a layer are printed.
+pub struct KeyboardLayout {
+    /// The cell of each key, in the order of the bindings.
+    pub keys: Vec<KeyPosition>,
+    /// Columns that are followed by an empty gutter column, e.g. to separate
+    /// the halves of a split keyboard.
+    pub gutters: Vec<usize>,
+}
+
+impl KeyboardLayout {
+    /// Creates a layout from the position of each key, checking that no two
+    /// keys share a cell.
+    pub fn new(
+        keys: Vec<KeyPosition>,
+        gutters: Vec<usize>,
+    ) -> Result<Self, String> {
+        if keys.is_empty() {
+            return Err("the layout must have at least one key".to_owned());
+        }
+
+        let mut cells = HashSet::new();
+        for key in &keys {
+            if !cells.insert(key) {
+                return Err(format!(
+                    "more than one key is at column {} of row {}",
+                    key.x, key.y
+                ));
+            }
+        }
+
+        let layout = Self { keys, gutters };
+        let column_count = layout.column_count();
+
+        if let Some(gutter) =
+            layout.gutters.iter().find(|&&g| g >= column_count)
+        {
+            return Err(format!(
+                "gutter after column {} is outside the layout, which has {} \
+                 columns",
+                gutter, column_count
+            ));
+        }
+
+        Ok(layout)
+    }
+
+    /// Creates a layout from a grid where `1` is a key and `0` is an empty
+    /// space. The keys are ordered from left to right and top to bottom.
+    pub fn from_grid(grid: &[Vec<usize>]) -> Self {
+        let keys = grid
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.iter()
+                    .enumerate()
+                    .filter(|(_, &is_key)| is_key == 1)
+                    .map(move |(x, _)| KeyPosition { x, y })
+            })
+            .collect();
+
+        Self { keys, gutters: Vec::new() }
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.keys.iter().map(|key| key.y + 1).max().unwrap_or(0)
+    }
+
+    pub fn column_count(&self) -> usize {
+        self.keys.iter().map(|key| key.x + 1).max().unwrap_or(0)
+    }
+
+    /// The number of keys in the layout, i.e. how many bindings a layer
+    /// should have.
+    pub fn key_count(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Places the items in a table at the positions of their keys, leaving
+    /// empty cells for the gaps and gutters of the layout.
+    pub fn table<T: Clone + Default>(
+        &self,
+        items: impl IntoIterator<Item = T>,
+    ) -> Vec<Vec<T>> {
+        let mut rows =
+            vec![vec![T::default(); self.column_count()]; self.row_count()];
+
+        for (key, item) in self.keys.iter().zip(items) {
+            rows[key.y][key.x] = item;
+        }
+
+        let mut gutters = self.gutters.clone();
+        gutters.sort_unstable();
+        gutters.dedup();
+
+        for row in &mut rows {
+            for &gutter in gutters.iter().rev() {
+                row.insert(gutter + 1, T::default());
+            }
+        }
+
+        rows
+    }
+
+    /// Returns the layout as a grid where `1` is a key and `0` is an empty
+    /// space or gutter.
+    pub fn grid(&self) -> Vec<Vec<usize>> {
+        self.table(std::iter::repeat(1))
+    }
+
+    /// Draws the layout as ASCII art with a `[ ]` for each key.
+    pub fn render(&self) -> String {
+        self.grid()
+            .iter()
+            .map(|row| {
+                let row = row
+                    .iter()
+                    .map(|&is_key| if is_key == 1 { "[ ]" } else { "   " })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+
+                format!("{}\n", row.trim_end())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_places_keys_in_a_table() {
+        let key = |x, y| KeyPosition { x, y };
+
+        // The thumb key is listed first but printed under the second column.
+        let layout = KeyboardLayout::new(
+            vec![key(1, 1), key(0, 0), key(1, 0), key(2, 0)],
+            vec![1],
+        )
+        .unwrap();
+
+        assert_eq!(
+            layout.table(["T", "A", "B", "C"]),
+            vec![vec!["A", "B", "", "C"], vec!["", "T", "", ""]]
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_layouts() {
+        let key = |x, y| KeyPosition { x, y };
+
+        assert!(KeyboardLayout::new(vec![], vec![]).is_err());
+        assert!(
+            KeyboardLayout::new(vec![key(0, 0), key(0, 0)], vec![]).is_err()
+        );
+        assert!(
+            KeyboardLayout::new(vec![key(0, 0), key(1, 0)], vec![2]).is_err()
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use super::document::Document;
+use super::{KeyPosition, KeyboardLayout};
+
+const COMPATIBLE: &str = "zmk,matrix-transform";
+
+/// Builds a layout from the `RC(row, column)` entries of the keymap's matrix
+/// transform, which is found either in the document itself or in one of the
+/// files it includes.
+pub fn get_layout(document: &Document) -> Result<KeyboardLayout, String> {
+    let map = document.chosen_property(COMPATIBLE, "map").ok_or_else(|| {
+        format!("no `{}` node with a map was found", COMPATIBLE)
+    })?;
+
+    from_coordinates(&parse_map(&map)?)
+}
+
+/// Parses the `RC(row, column)` entries of a matrix transform map.
+fn parse_map(map: &str) -> Result<Vec<(usize, usize)>, String> {
+    let coordinates = map
+        .split("RC(")
+        .skip(1)
+        .map(|entry| {
+            let args = entry.split(')').next().unwrap_or_default();
+
+            args.split_once(',')
+                .and_then(|(row, col)| {
+                    Some((row.trim().parse().ok()?, col.trim().parse().ok()?))
+                })
+                .ok_or_else(|| {
+                    format!("invalid matrix transform entry `RC({})`", args)
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if coordinates.is_empty() {
+        return Err("the matrix transform map has no RC() entries".to_owned());
+    }
+
+    Ok(coordinates)
+}
+
+/// Places each binding at the row and column of its matrix transform entry,
+/// so the entries can be listed in any order.
+fn from_coordinates(
+    coordinates: &[(usize, usize)],
+) -> Result<KeyboardLayout, String> {
+    let keys = coordinates
+        .iter()
+        .map(|&(row, col)| KeyPosition { x: col, y: row })
+        .collect();
+
+    KeyboardLayout::new(keys, Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_builds_layouts_from_coordinates() {
+        let coordinates =
+            parse_map("<RC(0,0) RC(0,2)\n RC(1,0) RC( 1 , 1 ) RC(1,2)>")
+                .unwrap();
+        let layout = from_coordinates(&coordinates).unwrap();
+        assert_eq!(layout.grid(), vec![vec![1, 0, 1], vec![1, 1, 1]]);
+
+        // Thumb keys are often listed after the keys of the other rows.
+        let layout = from_coordinates(&[(0, 0), (1, 1), (0, 1)]).unwrap();
+        assert_eq!(
+            layout.table(["A", "T", "B"]),
+            vec![vec!["A", "B"], vec!["", "T"]]
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_maps() {
+        assert!(parse_map("<>").is_err());
+        assert!(parse_map("<RC(0,a)>").is_err());
+        assert!(from_coordinates(&[(0, 1), (0, 1)]).is_err());
+    }
+}
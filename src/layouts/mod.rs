@@ -1,41 +1,124 @@
-use serde::{Deserialize, Serialize};
-
-mod adv360;
-mod glove80;
-mod imprint_letters_only_full_bottom_row;
-mod sweep;
-
-#[derive(Serialize, Deserialize, Default)]
-pub enum KeyboardLayoutType {
-    #[serde(rename = "kinesis:adv360")]
-    #[default]
-    Adv360,
-    #[serde(rename = "sweep")]
-    Sweep,
-    #[serde(rename = "moergo:glove80")]
-    Glove80,
-    #[serde(rename = "imprint:letters_only_full_bottom_row")]
-    ImprintLettersOnlyFullBottomRow,
+use std::collections::BTreeMap;
+
+use serde::de::{value, Error, IntoDeserializer};
+use serde::{Deserialize, Deserializer};
+
+pub use self::catalog::{catalog, find as find_builtin, BuiltinLayout};
+pub use self::custom::*;
+pub use self::detect::{detect_layout, AutoLayout, Detection, DetectionRule};
+pub use self::document::Document;
+pub use self::import::LayoutFileFormat;
+pub use self::keyboard_layout::{KeyPosition, KeyboardLayout};
+
+mod catalog;
+mod custom;
+mod detect;
+mod document;
+mod import;
+mod keyboard_layout;
+mod matrix_transform;
+mod physical_layout;
+
+/// Layouts that are derived from the file being formatted.
+#[derive(Clone, Deserialize)]
+pub enum LayoutMode {
+    /// Keep the rows of each layer as written and only align the columns.
+    #[serde(rename = "preserve")]
+    Preserve,
+    /// Pick a bundled or custom layout based on the file, or keep the rows as
+    /// written if none matches.
+    #[serde(rename = "auto")]
+    Auto,
+    #[serde(rename = "auto:matrix-transform")]
+    MatrixTransform,
+    #[serde(rename = "auto:physical-layout")]
+    PhysicalLayout,
 }
 
-pub struct KeyboardLayout {
-    pub bindings: Vec<usize>,
-    pub row_count: usize,
+/// The `layout` config option, which is either a layout derived from the
+/// file, the name of a bundled or custom layout, or a custom layout defined
+/// inline.
+#[derive(Clone)]
+pub enum LayoutConfig {
+    Mode(LayoutMode),
+    Named(String),
+    Inline(CustomLayout),
 }
 
-impl KeyboardLayout {
-    pub fn row_size(&self) -> usize {
-        self.bindings.len() / self.row_count
+impl<'de> Deserialize<'de> for LayoutConfig {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        match toml::Value::deserialize(deserializer)? {
+            toml::Value::String(name) => Ok(Self::from(name.as_str())),
+            toml::Value::Table(table) => {
+                CustomLayout::deserialize(toml::Value::Table(table))
+                    .map(Self::Inline)
+                    .map_err(|err| D::Error::custom(err.message()))
+            }
+            other => Err(D::Error::custom(format!(
+                "expected the name of a layout or a table, found {}",
+                other.type_str()
+            ))),
+        }
     }
 }
 
-pub fn get_layout(layout_type: &KeyboardLayoutType) -> KeyboardLayout {
-    match layout_type {
-        KeyboardLayoutType::Adv360 => adv360::get_layout(),
-        KeyboardLayoutType::Sweep => sweep::get_layout(),
-        KeyboardLayoutType::Glove80 => glove80::get_layout(),
-        KeyboardLayoutType::ImprintLettersOnlyFullBottomRow => {
-            imprint_letters_only_full_bottom_row::get_layout()
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self::Mode(LayoutMode::Preserve)
+    }
+}
+
+impl From<&str> for LayoutConfig {
+    fn from(name: &str) -> Self {
+        let mode: Result<LayoutMode, value::Error> =
+            LayoutMode::deserialize(name.into_deserializer());
+
+        mode.map_or_else(|_| Self::Named(name.to_owned()), Self::Mode)
+    }
+}
+
+/// Resolves the configured layout for the given document, looking up named
+/// layouts in the custom layouts defined in the config file and then in the
+/// bundled layouts. Returns `None` when the rows of the bindings should be
+/// kept as written.
+pub fn resolve_layout(
+    layout: &LayoutConfig,
+    custom_layouts: &BTreeMap<String, CustomLayout>,
+    document: &Document,
+) -> Result<Option<KeyboardLayout>, String> {
+    let layout = match layout {
+        // Detected layouts are resolved by name, so an `auto` layout only gets
+        // here if no layout matched the file.
+        LayoutConfig::Mode(LayoutMode::Preserve | LayoutMode::Auto) => {
+            return Ok(None)
+        }
+        LayoutConfig::Mode(LayoutMode::MatrixTransform) => {
+            matrix_transform::get_layout(document)?
         }
+        LayoutConfig::Mode(LayoutMode::PhysicalLayout) => {
+            physical_layout::get_layout(document)?
+        }
+        LayoutConfig::Inline(custom) => custom
+            .to_layout()
+            .map_err(|err| format!("invalid layout: {}", err))?,
+        LayoutConfig::Named(name) => resolve_named(name, custom_layouts)?,
+    };
+
+    Ok(Some(layout))
+}
+
+/// Finds the custom or bundled layout with the given name.
+pub fn resolve_named(
+    name: &str,
+    custom_layouts: &BTreeMap<String, CustomLayout>,
+) -> Result<KeyboardLayout, String> {
+    match (custom_layouts.get(name), catalog::find(name)) {
+        (Some(custom), _) => custom
+            .to_layout()
+            .map_err(|err| format!("invalid layout `{}`: {}", name, err)),
+        (None, Some(builtin)) => Ok(builtin.to_layout()),
+        (None, None) => Err(format!("unknown layout `{}`", name)),
     }
 }
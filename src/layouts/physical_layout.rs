@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+
+use super::document::Document;
+use super::{KeyPosition, KeyboardLayout};
+
+pub(super) const COMPATIBLE: &str = "zmk,physical-layout";
+
+/// The size of a 1u key in the centi-keyunits used by ZMK.
+const KEY_UNIT: f64 = 100.0;
+
+/// The attributes of a `&key_physical_attrs` entry. Sizes and positions are
+/// in centi-keyunits and the rotation is in centi-degrees.
+#[derive(Debug, PartialEq)]
+pub(super) struct PhysicalKey {
+    pub w: f64,
+    pub h: f64,
+    pub x: f64,
+    pub y: f64,
+    pub r: f64,
+    pub rx: f64,
+    pub ry: f64,
+}
+
+impl PhysicalKey {
+    /// The position of the center of the key after rotation.
+    fn center(&self) -> (f64, f64) {
+        let x = self.x + self.w / 2.0;
+        let y = self.y + self.h / 2.0;
+
+        if self.r == 0.0 {
+            return (x, y);
+        }
+
+        // Rotations are clockwise around (rx, ry) with y pointing down.
+        let angle = (self.r / 100.0).to_radians();
+        let (dx, dy) = (x - self.rx, y - self.ry);
+
+        (
+            self.rx + dx * angle.cos() - dy * angle.sin(),
+            self.ry + dx * angle.sin() + dy * angle.cos(),
+        )
+    }
+}
+
+/// Builds a layout from the key positions of the keymap's physical layout,
+/// which is found either in the document itself or in one of the files it
+/// includes.
+pub fn get_layout(document: &Document) -> Result<KeyboardLayout, String> {
+    let keys =
+        document.chosen_property(COMPATIBLE, "keys").ok_or_else(|| {
+            format!("no `{}` node with keys was found", COMPATIBLE)
+        })?;
+
+    Ok(from_keys(&parse_keys(&keys)?))
+}
+
+/// Parses the `&key_physical_attrs w h x y r rx ry` entries of a physical
+/// layout.
+fn parse_keys(keys: &str) -> Result<Vec<PhysicalKey>, String> {
+    let keys = keys
+        .split('&')
+        .skip(1)
+        .map(|entry| {
+            let entry = entry.split('>').next().unwrap_or_default().trim();
+            let values = entry
+                .split_whitespace()
+                .skip(1)
+                .map(|value| {
+                    value.trim_matches(|c| c == '(' || c == ')').parse::<f64>()
+                })
+                .collect::<Result<Vec<_>, _>>();
+
+            match values.as_deref() {
+                Ok(&[w, h, x, y, r, rx, ry]) => {
+                    Ok(PhysicalKey { w, h, x, y, r, rx, ry })
+                }
+                _ => Err(format!("invalid physical layout key `&{}`", entry)),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if keys.is_empty() {
+        return Err("the physical layout has no keys".to_owned());
+    }
+
+    Ok(keys)
+}
+
+/// Quantizes the key positions into a grid. Keys are placed in the column
+/// nearest to their center after rotation, and in rows counted down each
+/// column, so that column-staggered keyboards, the halves of a split keyboard
+/// and thumb clusters line up no matter the order the keys are listed in.
+pub(super) fn from_keys(keys: &[PhysicalKey]) -> KeyboardLayout {
+    let centers = keys.iter().map(PhysicalKey::center).collect::<Vec<_>>();
+    let min_x = centers.iter().map(|c| c.0).fold(f64::INFINITY, f64::min);
+    let min_y = centers.iter().map(|c| c.1).fold(f64::INFINITY, f64::min);
+
+    let quantize = |value: f64, min: f64| ((value - min) / KEY_UNIT).round();
+
+    let mut columns: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for (i, &(x, _)) in centers.iter().enumerate() {
+        columns.entry(quantize(x, min_x) as usize).or_default().push(i);
+    }
+
+    let mut rows = vec![0; keys.len()];
+    for column in columns.values_mut() {
+        column.sort_by(|&a, &b| centers[a].1.total_cmp(&centers[b].1));
+
+        // Columns are staggered by less than a key, so a column whose top key
+        // is lower than the others still starts in the top row. The rows below
+        // are counted from the key above, which cancels out the stagger.
+        let top = centers[column[0]].1;
+        let mut row = ((top - min_y) / KEY_UNIT + 0.1).floor() as usize;
+        let mut prev_y = top;
+
+        for &i in column.iter() {
+            row += quantize(centers[i].1, prev_y) as usize;
+            rows[i] = row;
+            prev_y = centers[i].1;
+        }
+    }
+
+    // Rows without any keys are left out of the grid.
+    let mut row_numbers = rows.clone();
+    row_numbers.sort_unstable();
+    row_numbers.dedup();
+
+    let mut positions = vec![KeyPosition { x: 0, y: 0 }; keys.len()];
+
+    for (y, &row) in row_numbers.iter().enumerate() {
+        let mut row_keys =
+            (0..keys.len()).filter(|&i| rows[i] == row).collect::<Vec<_>>();
+        row_keys.sort_by(|&a, &b| centers[a].0.total_cmp(&centers[b].0));
+
+        let mut next_col = 0;
+        for i in row_keys {
+            // Keys wider than 1u can round to the same column as their
+            // neighbor, so make sure each key gets its own column.
+            let x = (quantize(centers[i].0, min_x) as usize).max(next_col);
+            positions[i] = KeyPosition { x, y };
+            next_col = x + 1;
+        }
+    }
+
+    KeyboardLayout { keys: positions, gutters: Vec::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_keys() {
+        let keys = parse_keys(
+            "<&key_physical_attrs 100 100 0 37 0 0 0>,\n  \
+             <&key_physical_attrs 150 100 (-50) 0 (-1500) 100 200>",
+        )
+        .unwrap();
+
+        assert_eq!(keys.len(), 2);
+        assert_eq!(
+            keys[1],
+            PhysicalKey {
+                w: 150.0,
+                h: 100.0,
+                x: -50.0,
+                y: 0.0,
+                r: -1500.0,
+                rx: 100.0,
+                ry: 200.0
+            }
+        );
+
+        assert!(parse_keys("<&key_physical_attrs 100 100 0 0>").is_err());
+        assert!(parse_keys("<>").is_err());
+    }
+
+    #[test]
+    fn it_quantizes_keys() {
+        let key =
+            |x, y, r| PhysicalKey { w: 100.0, h: 100.0, x, y, r, rx: x, ry: y };
+
+        // Two staggered rows of two keys per half with a rotated thumb key
+        // on each side.
+        let keys = [
+            key(0.0, 20.0, 0.0),
+            key(100.0, 0.0, 0.0),
+            key(400.0, 0.0, 0.0),
+            key(500.0, 20.0, 0.0),
+            key(0.0, 120.0, 0.0),
+            key(100.0, 100.0, 0.0),
+            key(400.0, 100.0, 0.0),
+            key(500.0, 120.0, 0.0),
+            key(200.0, 220.0, 1500.0),
+            key(300.0, 220.0, -1500.0),
+        ];
+
+        let layout = from_keys(&keys);
+
+        assert_eq!(
+            layout.grid(),
+            vec![
+                vec![1, 1, 0, 0, 1, 1],
+                vec![1, 1, 0, 0, 1, 1],
+                vec![0, 0, 1, 1, 0, 0],
+            ]
+        );
+    }
+
+    #[test]
+    fn it_quantizes_keys_by_half() {
+        let key =
+            |x, y, r| PhysicalKey { w: 100.0, h: 100.0, x, y, r, rx: x, ry: y };
+
+        // The left half is listed before the right half and the thumb keys
+        // are listed from right to left.
+        let keys = [
+            key(0.0, 0.0, 0.0),
+            key(100.0, 0.0, 0.0),
+            key(0.0, 100.0, 0.0),
+            key(100.0, 100.0, 0.0),
+            key(400.0, 0.0, 0.0),
+            key(500.0, 0.0, 0.0),
+            key(400.0, 100.0, 0.0),
+            key(500.0, 100.0, 0.0),
+            key(300.0, 220.0, -1500.0),
+            key(200.0, 220.0, 1500.0),
+        ];
+
+        let layout = from_keys(&keys);
+
+        assert_eq!(
+            layout.grid(),
+            vec![
+                vec![1, 1, 0, 0, 1, 1],
+                vec![1, 1, 0, 0, 1, 1],
+                vec![0, 0, 1, 1, 0, 0],
+            ]
+        );
+        assert_eq!(
+            layout.table(0..keys.len()),
+            vec![
+                vec![0, 1, 0, 0, 4, 5],
+                vec![2, 3, 0, 0, 6, 7],
+                vec![0, 0, 9, 8, 0, 0],
+            ]
+        );
+    }
+
+    #[test]
+    fn it_quantizes_staggered_columns() {
+        let key = |x, y| PhysicalKey {
+            w: 100.0,
+            h: 100.0,
+            x,
+            y,
+            r: 0.0,
+            rx: 0.0,
+            ry: 0.0,
+        };
+
+        // Kyria-style columns where the outer columns are staggered down by
+        // 0.75u and 0.5u, with two thumb keys under the inner columns.
+        let mut keys = Vec::new();
+        for row in 0..3 {
+            for (x, stagger) in
+                [(0.0, 75.0), (100.0, 50.0), (200.0, 0.0), (300.0, 25.0)]
+            {
+                keys.push(key(x, stagger + row as f64 * 100.0));
+            }
+        }
+        keys.push(key(200.0, 320.0));
+        keys.push(key(300.0, 320.0));
+
+        assert_eq!(
+            from_keys(&keys).grid(),
+            vec![
+                vec![1, 1, 1, 1],
+                vec![1, 1, 1, 1],
+                vec![1, 1, 1, 1],
+                vec![0, 0, 1, 1],
+            ]
+        );
+    }
+}
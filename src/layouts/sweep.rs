@@ -1,13 +0,0 @@
-use super::KeyboardLayout;
-
-pub fn get_layout() -> KeyboardLayout {
-    #[rustfmt::skip]
-    let bindings = vec![
-      1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
-      1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
-      1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
-      0, 0, 0, 1, 1, 1, 1, 0, 0, 0,
-    ];
-
-    KeyboardLayout { bindings, row_count: 4 }
-}
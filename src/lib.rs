@@ -1,8 +1,15 @@
+mod comments;
 pub mod config;
 mod context;
+pub mod diagnostics;
+pub mod doc;
 pub mod emitter;
 pub mod layouts;
+mod literals;
+mod macros;
 mod parser;
 pub mod printer;
+mod sort;
 pub mod test_utils;
+mod tokens;
 mod utils;
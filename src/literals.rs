@@ -0,0 +1,143 @@
+use crate::config::{HexCase, Radix};
+
+/// The value of an integer literal, or `None` if it isn't a plain decimal or
+/// hexadecimal number, e.g. an octal number or a number with a suffix.
+pub fn integer_value(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(digits) => u64::from_str_radix(digits, 16).ok(),
+        None if text == "0" => Some(0),
+        None if text.starts_with('0') => None,
+        None if text.bytes().all(|b| b.is_ascii_digit()) => text.parse().ok(),
+        None => None,
+    }
+}
+
+/// Formats an integer literal in the given radix, or in the radix it was
+/// written in if there is none. Literals that aren't plain numbers are kept
+/// as written.
+pub fn format_integer(
+    text: &str,
+    radix: Option<Radix>,
+    case: HexCase,
+) -> String {
+    let Some(value) = integer_value(text) else {
+        return text.to_owned();
+    };
+
+    let hex = text.starts_with("0x") || text.starts_with("0X");
+
+    match radix {
+        Some(Radix::Decimal) => value.to_string(),
+        Some(Radix::Hex) if !hex => {
+            format!("0x{}", format_hex_digits(&format!("{:x}", value), case))
+        }
+        _ if hex && case != HexCase::Preserve => {
+            format!("0x{}", format_hex_digits(&text[2..], case))
+        }
+        _ => text.to_owned(),
+    }
+}
+
+/// Changes the case of hexadecimal digits, such as the bytes of a byte string
+/// or a unit address.
+pub fn format_hex_digits(text: &str, case: HexCase) -> String {
+    match case {
+        HexCase::Preserve => text.to_owned(),
+        HexCase::Lower => text.to_ascii_lowercase(),
+        HexCase::Upper => text.to_ascii_uppercase(),
+    }
+}
+
+/// Splits the text of a byte string into its bytes, with the case of their
+/// digits changed. Bytes that were written together, as in `[deadbeef]`, are
+/// split apart.
+pub fn split_bytes(text: &str, case: HexCase) -> Vec<String> {
+    let mut bytes = Vec::new();
+
+    for word in text.split_whitespace() {
+        let hex =
+            word.len() % 2 == 0 && word.bytes().all(|b| b.is_ascii_hexdigit());
+
+        match hex {
+            true => bytes.extend(
+                (0..word.len())
+                    .step_by(2)
+                    .map(|i| format_hex_digits(&word[i..i + 2], case)),
+            ),
+            false => bytes.push(format_hex_digits(word, case)),
+        }
+    }
+
+    bytes
+}
+
+/// Changes the case of a unit address such as `4000a000` or `1,0`, unless it
+/// contains anything but hexadecimal numbers, like a macro.
+pub fn format_unit_address(text: &str, case: HexCase) -> String {
+    let numeric = text.split(',').all(|part| {
+        !part.is_empty() && part.bytes().all(|b| b.is_ascii_hexdigit())
+    });
+
+    match numeric {
+        true => format_hex_digits(text, case),
+        false => text.to_owned(),
+    }
+}
+
+/// Changes the case of the unit address of each node in a path such as
+/// `/soc/uart@4000A000`, so that path references keep matching the nodes they
+/// refer to.
+pub fn format_path(text: &str, case: HexCase) -> String {
+    text.split('/')
+        .map(|segment| match segment.split_once('@') {
+            Some((name, address)) => {
+                format!("{}@{}", name, format_unit_address(address, case))
+            }
+            None => segment.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_formats_integers() {
+        let lower = HexCase::Lower;
+        let upper = HexCase::Upper;
+
+        assert_eq!(format_integer("0XaB", None, upper), "0xAB");
+        assert_eq!(format_integer("0XAb", None, HexCase::Preserve), "0XAb");
+        assert_eq!(format_integer("4096", Some(Radix::Hex), lower), "0x1000");
+        assert_eq!(format_integer("0x1F", Some(Radix::Decimal), lower), "31");
+        assert_eq!(format_integer("31", None, upper), "31");
+        assert_eq!(format_integer("0755", Some(Radix::Hex), lower), "0755");
+        assert_eq!(format_integer("10U", Some(Radix::Hex), lower), "10U");
+    }
+
+    #[test]
+    fn it_splits_bytes() {
+        assert_eq!(
+            split_bytes("DEADbeef 00\n 1", HexCase::Lower),
+            vec!["de", "ad", "be", "ef", "00", "1"]
+        );
+    }
+
+    #[test]
+    fn it_formats_unit_addresses() {
+        assert_eq!(format_unit_address("4000a000", HexCase::Upper), "4000A000");
+        assert_eq!(format_unit_address("1,f", HexCase::Upper), "1,F");
+        assert_eq!(format_unit_address("ADDR_x", HexCase::Lower), "ADDR_x");
+    }
+
+    #[test]
+    fn it_formats_paths() {
+        assert_eq!(
+            format_path("/soc/UART@4000A000/Port@1F", HexCase::Lower),
+            "/soc/UART@4000a000/Port@1f"
+        );
+        assert_eq!(format_path("/", HexCase::Upper), "/");
+    }
+}
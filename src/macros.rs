@@ -0,0 +1,218 @@
+use std::mem;
+
+use crate::config::{Config, MacroConfig};
+
+/// A call of a macro that generates nodes, e.g. `ZMK_LAYER(base, ...)`.
+pub struct MacroCall<'a> {
+    pub name: &'a str,
+    pub config: MacroConfig,
+    /// The arguments before the properties or bindings.
+    pub args: Vec<&'a str>,
+    /// The properties or bindings, which may be empty.
+    pub body: &'a str,
+    /// Whether the call is followed by a semicolon.
+    pub semicolon: bool,
+}
+
+impl<'a> MacroCall<'a> {
+    /// Parses the text of a macro call, if it calls one of the configured
+    /// macros.
+    pub fn parse(text: &'a str, config: &Config) -> Option<Self> {
+        let (name, rest) = text.split_once('(')?;
+        let name = name.trim();
+        let macro_config = config.macro_config(name)?;
+
+        let close = find_closing_paren(rest)?;
+        let semicolon = match rest[close + 1..].trim() {
+            "" => false,
+            ";" => true,
+            _ => return None,
+        };
+
+        let mut args = Vec::new();
+        let mut body = &rest[..close];
+
+        for _ in 0..macro_config.args {
+            match split_arg(body) {
+                Some((arg, rest)) => {
+                    args.push(arg.trim());
+                    body = rest;
+                }
+                None => {
+                    args.push(body.trim());
+                    body = "";
+                    break;
+                }
+            }
+        }
+
+        Some(Self {
+            name,
+            config: macro_config,
+            args,
+            body: body.trim(),
+            semicolon,
+        })
+    }
+}
+
+/// Finds the parenthesis that closes the call, given the text after the
+/// opening one.
+fn find_closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 1;
+    let mut in_string = false;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Splits the first argument off the arguments of a call.
+fn split_arg(text: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    let mut in_string = false;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => return Some((&text[..i], &text[i + 1..])),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Splits the bindings argument of a layer macro into rows by the line each
+/// binding starts on, like the bindings of a keymap node. Bindings with
+/// comments are left as written, since a comment can't be moved safely.
+pub fn bindings_rows(body: &str) -> Option<Vec<Vec<String>>> {
+    if body.contains("//") || body.contains("/*") {
+        return None;
+    }
+
+    let mut rows = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+
+    for line in body.lines() {
+        let mut new_line = true;
+
+        for word in split_words(line) {
+            match row.last_mut() {
+                // The parameters of a binding may continue on the next line.
+                Some(item) if !word.starts_with('&') => {
+                    item.push(' ');
+                    item.push_str(word);
+                }
+                _ => {
+                    if new_line && !row.is_empty() {
+                        rows.push(mem::take(&mut row));
+                    }
+
+                    row.push(word.to_owned());
+                    new_line = false;
+                }
+            }
+        }
+    }
+
+    if !row.is_empty() {
+        rows.push(row);
+    }
+
+    Some(rows)
+}
+
+/// Splits a line on whitespace that isn't inside parentheses, which keeps
+/// parameters like `LS(A)` together.
+fn split_words(line: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut depth = 0;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            c if c.is_whitespace() && depth == 0 => {
+                if let Some(start) = start.take() {
+                    words.push(&line[start..i]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        start.get_or_insert(i);
+    }
+
+    if let Some(start) = start {
+        words.push(&line[start..]);
+    }
+
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MacroKind;
+
+    #[test]
+    fn it_parses_macro_calls() {
+        let config = Config::default();
+
+        let call = MacroCall::parse(
+            "ZMK_HOLD_TAP(hm, bindings = <&kp>, <&kp>; flavor = \"balanced\";)",
+            &config,
+        )
+        .unwrap();
+        assert_eq!(call.config.kind, MacroKind::Properties);
+        assert_eq!(call.args, vec!["hm"]);
+        assert_eq!(
+            call.body,
+            "bindings = <&kp>, <&kp>; flavor = \"balanced\";"
+        );
+        assert!(!call.semicolon);
+
+        let call =
+            MacroCall::parse("ZMK_LAYER(base, &kp A);", &config).unwrap();
+        assert_eq!(call.args, vec!["base"]);
+        assert_eq!(call.body, "&kp A");
+        assert!(call.semicolon);
+
+        assert!(MacroCall::parse("OTHER(base, &kp A)", &config).is_none());
+        assert!(MacroCall::parse("ZMK_LAYER(base, &kp A", &config).is_none());
+    }
+
+    #[test]
+    fn it_splits_bindings_into_rows() {
+        let rows =
+            bindings_rows("&kp A &mt LSHIFT\n  B &kp LS(C)\n&trans").unwrap();
+
+        assert_eq!(
+            rows,
+            vec![
+                vec!["&kp A", "&mt LSHIFT B"],
+                vec!["&kp LS(C)"],
+                vec!["&trans"]
+            ]
+        );
+        assert!(bindings_rows("&kp A // comment").is_none());
+    }
+}
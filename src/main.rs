@@ -2,9 +2,11 @@ use std::fs;
 use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use dtsfmt::config::Config;
 use dtsfmt::emitter::{create_emitter, Emitter, FormattedFile};
+use dtsfmt::layouts::{self, LayoutConfig, LayoutMode};
+use dtsfmt::printer::Formatted;
 use ignore::gitignore::GitignoreBuilder;
 use ignore::types::TypesBuilder;
 use ignore::WalkBuilder;
@@ -13,6 +15,24 @@ use ignore::WalkBuilder;
 enum FormattingStatus {
     Changed,
     Unchanged,
+    Failed,
+}
+
+/// Tracks the overall result of formatting one or more files.
+#[derive(Default)]
+struct Summary {
+    changed: bool,
+    failed: bool,
+}
+
+impl Summary {
+    fn record(&mut self, status: FormattingStatus) {
+        match status {
+            FormattingStatus::Changed => self.changed = true,
+            FormattingStatus::Failed => self.failed = true,
+            FormattingStatus::Unchanged => {}
+        }
+    }
 }
 
 #[derive(Clone, Parser)]
@@ -26,14 +46,39 @@ struct Cli {
     #[arg(long)]
     stdin: bool,
 
+    /// Report the layout picked for each file when the layout is `auto`
+    #[arg(long, short)]
+    verbose: bool,
+
     /// The file to format
     #[arg(index = 1, value_name = "FILE")]
     file_path: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Clone, Subcommand)]
+enum Command {
+    /// Inspect the layouts used to print keymap bindings
+    #[command(subcommand)]
+    Layouts(LayoutsCommand),
 }
 
-fn format_fs(cli: &Cli, config: &Config, dir_path: &Path) -> bool {
+#[derive(Clone, Subcommand)]
+enum LayoutsCommand {
+    /// List the bundled layouts and the custom layouts in the config file
+    List,
+    /// Draw the keys of a layout
+    Show {
+        /// The name of a bundled or custom layout
+        name: String,
+    },
+}
+
+fn format_fs(cli: &Cli, config: &Config, dir_path: &Path) -> Summary {
     let mut emitter = create_emitter(false);
-    let mut has_errors = false;
+    let mut summary = Summary::default();
 
     let mut types = TypesBuilder::new();
     types.add_defaults();
@@ -55,16 +100,23 @@ fn format_fs(cli: &Cli, config: &Config, dir_path: &Path) -> bool {
         let path = result.path();
         let buffer = fs::read_to_string(path).expect("Failed to read file");
 
-        let status =
-            format(path.to_path_buf(), buffer, &mut emitter, config, cli.check);
-
-        has_errors |= status == FormattingStatus::Changed;
+        let status = format(
+            path.to_path_buf(),
+            Some(path),
+            buffer,
+            &mut emitter,
+            config,
+            cli.check,
+            cli.verbose,
+        );
+
+        summary.record(status);
     }
 
-    has_errors
+    summary
 }
 
-fn format_stdin(cli: &Cli, config: &Config, dir_path: &Path) -> bool {
+fn format_stdin(cli: &Cli, config: &Config, dir_path: &Path) -> Summary {
     let mut emitter = create_emitter(true);
     let mut buffer = String::new();
     io::stdin().read_to_string(&mut buffer).expect("Failed to read stdin");
@@ -74,10 +126,26 @@ fn format_stdin(cli: &Cli, config: &Config, dir_path: &Path) -> bool {
     let status = if is_ignored(dir_path) {
         print_original(cli, &mut emitter, &buffer)
     } else {
-        format(PathBuf::from("stdin"), buffer, &mut emitter, config, cli.check)
+        format(
+            PathBuf::from("stdin"),
+            cli.file_path.as_deref(),
+            buffer.clone(),
+            &mut emitter,
+            config,
+            cli.check,
+            cli.verbose,
+        )
     };
 
-    status == FormattingStatus::Changed
+    // Editors replace their buffer with whatever we print, so a file that
+    // failed to format must still be echoed back unchanged.
+    if status == FormattingStatus::Failed {
+        print_original(cli, &mut emitter, &buffer);
+    }
+
+    let mut summary = Summary::default();
+    summary.record(status);
+    summary
 }
 
 fn main() {
@@ -87,7 +155,15 @@ fn main() {
         Some(path) => path.clone(),
         None => std::env::current_dir().expect("Couldn't read CWD"),
     };
-    let config = Config::parse(&cfg_path);
+    let config = Config::parse(&cfg_path).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+
+    if let Some(Command::Layouts(command)) = &cli.command {
+        run_layouts_command(command, &config);
+        return;
+    }
 
     // If no path was specified (likely with --stdin) then default to the
     // current working directory.
@@ -96,16 +172,68 @@ fn main() {
         None => std::env::current_dir().expect("Couldn't read CWD"),
     };
 
-    let has_errors = if cli.stdin {
+    let summary = if cli.stdin {
         format_stdin(&cli, &config, &dir_path)
     } else {
         format_fs(&cli, &config, &dir_path)
     };
 
-    if cli.check && has_errors {
+    if cli.check && summary.changed {
         println!("\nErrors found while formatting!");
         std::process::exit(1);
     }
+
+    if summary.failed {
+        eprintln!("\nSome files could not be formatted!");
+        std::process::exit(1);
+    }
+}
+
+fn run_layouts_command(command: &LayoutsCommand, config: &Config) {
+    match command {
+        LayoutsCommand::List => {
+            let builtins = layouts::catalog().iter().map(|(name, layout)| {
+                let mut names = vec![name.as_str()];
+                names.extend(layout.aliases.iter().map(String::as_str));
+
+                (names.join(", "), layout.description.as_str())
+            });
+            let customs = config.layouts.keys().map(|name| {
+                let description = match layouts::find_builtin(name) {
+                    Some(_) => {
+                        "Custom layout from the config file, replacing \
+                                the bundled layout"
+                    }
+                    None => "Custom layout from the config file",
+                };
+
+                (name.clone(), description)
+            });
+            let rows = builtins.chain(customs).collect::<Vec<_>>();
+
+            let width = rows.iter().map(|(names, _)| names.len()).max();
+            for (names, description) in &rows {
+                println!(
+                    "{:width$}  {}",
+                    names,
+                    description,
+                    width = width.unwrap_or(0)
+                );
+            }
+        }
+        LayoutsCommand::Show { name } => {
+            match layouts::resolve_named(name, &config.layouts) {
+                Ok(layout) => {
+                    println!("{} ({} keys)\n", name, layout.key_count());
+                    print!("{}", layout.render());
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
 }
 
 /// Find all `.dtsfmtignore` files in the parent directories of the given path.
@@ -173,15 +301,41 @@ fn print_original(
     emit(emitter, file, buffer, buffer, cli.check)
 }
 
-/// Formats the given source code and emits the result.
+/// Formats the given source code and emits the result. The path is the file
+/// on disk the source belongs to, if any.
 fn format(
     filename: PathBuf,
+    path: Option<&Path>,
     source: String,
     emitter: &mut Box<dyn Emitter>,
     config: &Config,
     check: bool,
+    verbose: bool,
 ) -> FormattingStatus {
-    let output = dtsfmt::printer::print(&source, config);
+    let file_config = path.map(|path| config.for_file(path));
+    let config = file_config.as_ref().unwrap_or(config);
+
+    let output = match dtsfmt::printer::print_file(&source, config, path) {
+        Ok(formatted) => {
+            if verbose {
+                report_layout(&filename, config, &formatted);
+            }
+
+            for diagnostic in formatted.diagnostics {
+                eprintln!("{}:{}", filename.display(), diagnostic);
+            }
+
+            formatted.text
+        }
+        Err(diagnostics) => {
+            for diagnostic in diagnostics {
+                eprintln!("{}:{}", filename.display(), diagnostic);
+            }
+
+            return FormattingStatus::Failed;
+        }
+    };
+
     let result = FormattedFile {
         filename: &filename,
         original_text: &source,
@@ -191,6 +345,23 @@ fn format(
     emit(emitter, result, &output, &source, check)
 }
 
+/// Reports which layout was picked for a file when the layout is `auto`.
+fn report_layout(filename: &Path, config: &Config, formatted: &Formatted) {
+    if !matches!(config.layout, LayoutConfig::Mode(LayoutMode::Auto)) {
+        return;
+    }
+
+    match &formatted.detected_layout {
+        Some(detection) => {
+            eprintln!("{}: using layout {}", filename.display(), detection)
+        }
+        None => eprintln!(
+            "{}: no layout detected, keeping the rows as written",
+            filename.display()
+        ),
+    }
+}
+
 /// Emits the output of formatting either in check mode or by writing to the
 /// file.
 fn emit(
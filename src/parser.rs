@@ -1,4 +1,6 @@
-use tree_sitter::{Language, Parser, Tree};
+use tree_sitter::{Language, Node, Parser, Tree};
+
+use crate::diagnostics::Diagnostic;
 
 extern "C" {
     fn tree_sitter_devicetree() -> Language;
@@ -11,3 +13,43 @@ pub fn parse(source: String) -> Tree {
     parser.set_language(language).unwrap();
     parser.parse(source, None).unwrap()
 }
+
+/// Collects a diagnostic for every `ERROR` and `MISSING` node in the tree.
+pub fn syntax_errors(tree: &Tree, source: &str) -> Vec<Diagnostic> {
+    let mut errors = Vec::new();
+
+    if tree.root_node().has_error() {
+        collect_errors(&tree.root_node(), source, &mut errors);
+    }
+
+    errors
+}
+
+fn collect_errors(node: &Node, source: &str, errors: &mut Vec<Diagnostic>) {
+    if node.is_missing() {
+        let message = format!("missing `{}`", node.kind());
+        errors.push(Diagnostic::at_node(source, node, message));
+        return;
+    }
+
+    // Report the error node as a whole rather than each token inside of it.
+    if node.is_error() {
+        let text = node.utf8_text(source.as_bytes()).unwrap_or("").trim();
+        let message = match text.lines().next() {
+            Some(line) if !line.is_empty() => {
+                format!("unexpected `{}`", line.trim())
+            }
+            _ => "syntax error".to_owned(),
+        };
+
+        errors.push(Diagnostic::at_node(source, node, message));
+        return;
+    }
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if child.has_error() {
+            collect_errors(&child, source, errors);
+        }
+    }
+}
@@ -1,345 +1,1061 @@
-use std::collections::VecDeque;
+use std::cell::RefCell;
+use std::mem;
+use std::path::Path;
 
-use tree_sitter::TreeCursor;
+use tree_sitter::{Node, TreeCursor};
 
-use crate::config::Config;
+use crate::comments::reflow_comments;
+use crate::config::{
+    pragma, BindingMismatch, ByteStringColumns, ByteStringLayout, Config,
+    MacroKind, SortNodes,
+};
 use crate::context::Context;
-use crate::layouts;
-use crate::parser::parse;
-use crate::utils::{
-    get_text, lookahead, lookbehind, pad_right, print_indent, sep,
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::doc::{
+    self, concat, fill, group, hang, indent, join, line_suffix, text, Doc,
+};
+use crate::layouts::{self, Detection, Document};
+use crate::literals::{
+    format_hex_digits, format_integer, format_path, format_unit_address,
+    split_bytes,
 };
+use crate::macros::{bindings_rows, MacroCall};
+use crate::parser::{parse, syntax_errors};
+use crate::sort::{parse_unit_address, property_key};
+use crate::tokens;
+use crate::utils::{get_text, pad_right};
+
+/// The number of blank lines between the node and the node before it in the
+/// source, up to `max` lines.
+fn blank_lines(node: &Node, source: &str, max: usize) -> usize {
+    let Some(prev) = node.prev_sibling() else {
+        return 0;
+    };
 
-fn is_preproc(n: &tree_sitter::Node) -> bool {
-    n.kind() == "preproc_include"
-        || n.kind() == "preproc_ifdef"
-        || n.kind() == "preproc_def"
-        || n.kind() == "preproc_function_def"
+    let end = content_end(&prev, source);
+    let gap = source.get(end..node.start_byte()).unwrap_or_default();
+
+    gap.matches('\n').count().saturating_sub(1).min(max)
 }
 
-fn traverse(
-    writer: &mut String,
-    source: &String,
-    cursor: &mut TreeCursor,
-    ctx: &Context,
-) {
-    let node = cursor.node();
+/// The byte where the text of the node ends. Directives include the line
+/// break that ends them, which isn't part of their content.
+fn content_end(node: &Node, source: &str) -> usize {
+    let text = &source[node.start_byte()..node.end_byte()];
+    node.start_byte() + text.trim_end().len()
+}
+
+/// Normalizes a directive keyword such as `#  ifdef` to `#ifdef`.
+fn directive(text: &str) -> String {
+    format!("#{}", text.trim_start_matches('#').trim())
+}
+
+fn traverse(node: &Node, source: &String, ctx: &Context) -> Doc {
     let kind = node.kind();
 
     match kind {
-        "file_version" => {
-            writer.push_str(&format!("{}\n\n", get_text(source, cursor)));
-        }
-        "comment" => {
-            // Add a newline before the comment if the previous node is not a
-            // comment nor a '{'.
-            if lookbehind(cursor)
-                .is_some_and(|n| n.kind() != "comment" && n.kind() != "{")
-            {
-                sep(writer);
-            }
+        "file_version" => text(node_text(source, node)),
+        "comment" => print_comment(node, source, ctx),
+        "dtsi_include" | "preproc_include" => {
+            let keyword = match kind {
+                "dtsi_include" => "/include/",
+                _ => "#include",
+            };
+            let path = node.child(1).map(|n| node_text(source, &n));
 
-            print_indent(writer, ctx);
-            let comment = get_text(source, cursor);
-
-            // Only reformat single line comments, multi line comments are a
-            // lot tougher to format properly.
-            match comment.starts_with("//") {
-                true => {
-                    let trimmed_comment =
-                        comment.trim_start_matches("//").trim();
-                    if trimmed_comment.is_empty() {
-                        writer.push_str("//");
-                    } else {
-                        writer.push_str("// ");
-                        writer.push_str(trimmed_comment);
-                    }
+            text(format!("{} {}", keyword, path.unwrap_or_default()))
+        }
+        "preproc_def" | "preproc_function_def" => {
+            let mut header = String::from("#define ");
+
+            // Name, followed directly by the arguments of a function
+            for field in ["name", "parameters"] {
+                if let Some(child) = node.child_by_field_name(field) {
+                    header.push_str(node_text(source, &child));
                 }
-                false => writer.push_str(comment),
             }
 
-            writer.push('\n');
+            // Value, which may be omitted
+            match node.child_by_field_name("value") {
+                Some(value) => {
+                    let value = node_text(source, &value);
+                    text(print_define(header, value, ctx))
+                }
+                None => text(header),
+            }
         }
-        "dtsi_include" => {
-            cursor.goto_first_child();
-            print_indent(writer, ctx);
-            writer.push_str("/include/ ");
+        "preproc_call" => {
+            // Directives without a dedicated node, e.g. #undef, #pragma and
+            // #error.
+            let mut line = String::new();
 
-            cursor.goto_next_sibling();
-            writer.push_str(get_text(source, cursor));
-            writer.push('\n');
+            if let Some(name) = node.child_by_field_name("directive") {
+                line.push_str(&directive(node_text(source, &name)));
+            }
 
-            cursor.goto_parent();
+            if let Some(argument) = node.child_by_field_name("argument") {
+                line.push(' ');
+                line.push_str(node_text(source, &argument));
+            }
 
-            // Add a newline if this is the last dtsi_include
-            if lookahead(cursor).is_some_and(|n| n.kind() != "dtsi_include") {
-                writer.push('\n');
+            text(line)
+        }
+        "preproc_if" | "preproc_ifdef" => concat(vec![
+            print_branches(node, source, ctx),
+            Doc::HardLine,
+            text("#endif"),
+        ]),
+        "identifier" | "string_literal" => text(node_text(source, node)),
+        "path" => {
+            let path = node_text(source, node);
+            text(format_path(path, ctx.config.hex_case))
+        }
+        "unit_address" => {
+            let address = node_text(source, node);
+            text(format_unit_address(address, ctx.config.hex_case))
+        }
+        "reference" => {
+            // A reference has a format of "&label" or "&{path}".
+            let mut cursor = node.walk();
+            concat(
+                node.children(&mut cursor)
+                    .map(|child| traverse(&child, source, ctx))
+                    .collect(),
+            )
+        }
+        "node" | "property" | "delete_node" | "delete_property" => {
+            print_statement(node, source, ctx)
+        }
+        "byte_string_literal" => print_byte_string(node, source, ctx),
+        "integer_cells" => {
+            // Keymap bindings are a special snowflake
+            if ctx.has_zephyr_syntax() {
+                return print_bindings(node, source, ctx);
             }
+
+            text(format!("<{}>", cells(node, source, ctx.config).join(" ")))
         }
-        "preproc_include" => {
-            cursor.goto_first_child();
-            print_indent(writer, ctx);
-            writer.push_str("#include ");
+        // All the non-named grammatical tokens that are emitted but handled
+        // simply with some output structure.
+        "@" | "&" | "&{" | "}" | ";" => text(kind),
+        "{" => text(" {"),
+        ":" => text(": "),
+        "," => text(", "),
+        "=" => text(" = "),
+        "/delete-node/" | "/delete-property/" => text(format!("{} ", kind)),
+        _ => {
+            if let Some(doc) = print_macro_call(node, source, ctx) {
+                return doc;
+            }
+
+            if ctx.config.warn_on_unhandled_tokens {
+                eprintln!(
+                    "unhandled type '{}' ({} {}): {}",
+                    node.kind(),
+                    node.child_count(),
+                    if node.child_count() == 1 { "child" } else { "children" },
+                    node_text(source, node)
+                );
+            }
 
-            cursor.goto_next_sibling();
-            writer.push_str(get_text(source, cursor));
-            writer.push('\n');
+            // Since we're unsure of this node just traverse its children
+            let mut cursor = node.walk();
+            let mut children = node.children(&mut cursor);
+            let Some(first) = children.next() else {
+                return concat(vec![]);
+            };
+
+            let inner = ctx.inc(1);
+            let rest = children.map(|child| traverse(&child, source, &inner));
+            concat(vec![
+                traverse(&first, source, ctx),
+                indent(concat(rest.collect())),
+            ])
+        }
+    }
+}
 
-            cursor.goto_parent();
+/// A statement in the body of a node, a conditional branch or the file,
+/// with the comments at the end of its line.
+struct Statement<'a> {
+    doc: Doc,
+    comments: Vec<Node<'a>>,
+    /// The blank lines kept before the statement.
+    blank_lines: usize,
+    /// The name of the statement if it is a property.
+    property: Option<&'a str>,
+    /// The unit address of a node, or of the first node in a conditional
+    /// block, if it is a number.
+    address: Option<Vec<u64>>,
+    comment: bool,
+}
 
-            // Add a newline if this is the last preproc directive
-            if lookahead(cursor).is_some_and(|n| !is_preproc(&n)) {
-                writer.push('\n');
+/// Prints the statements of a block on their own lines, keeping the blank
+/// lines between them up to `max_blank_lines`. Each statement starts with a
+/// line break, except the first one unless `leading` is set. Trailing
+/// comments stay at the end of the line of the statement before them,
+/// aligned with each other if enabled.
+fn print_block<'a>(
+    children: &[Node<'a>],
+    source: &'a String,
+    ctx: &Context,
+    leading: bool,
+) -> Doc {
+    // Trailing comments of the line opening the block.
+    let mut header = Vec::new();
+    let mut statements: Vec<Statement> = Vec::new();
+
+    for child in children {
+        if is_trailing_comment(child, source) {
+            match statements.last_mut() {
+                Some(statement) => statement.comments.push(*child),
+                None => header.push(*child),
             }
+            continue;
         }
-        "preproc_def" => {
-            cursor.goto_first_child();
-            writer.push_str("#define ");
 
-            // Name
-            cursor.goto_next_sibling();
-            writer.push_str(get_text(source, cursor));
-            writer.push(' ');
+        // Runs of line comments are reflowed as a whole when printing the
+        // first comment of the run.
+        let width = ctx
+            .config
+            .comment_width
+            .filter(|_| is_own_line_comment(child, source));
+        let doc = match width {
+            Some(width) => match comment_run(child, source) {
+                Some(run) => print_comment_run(&run, width, source, ctx),
+                None => continue,
+            },
+            None => traverse(child, source, ctx),
+        };
+
+        // Blank lines at the start of the block are removed.
+        let blank_lines = match statements.is_empty() {
+            true => 0,
+            false => blank_lines(child, source, ctx.config.max_blank_lines),
+        };
 
-            // Value
-            cursor.goto_next_sibling();
-            writer.push_str(get_text(source, cursor));
+        let property = match child.kind() {
+            "property" => Some(property_name(child, source)),
+            _ => None,
+        };
 
-            writer.push('\n');
-            cursor.goto_parent();
+        statements.push(Statement {
+            doc,
+            comments: Vec::new(),
+            blank_lines,
+            property,
+            address: unit_address(child, source),
+            comment: child.kind() == "comment",
+        });
+    }
 
-            // Add a newline if this is the last preproc directive
-            if lookahead(cursor).is_some_and(|n| !is_preproc(&n)) {
-                writer.push('\n');
-            }
-        }
-        "preproc_function_def" => {
-            cursor.goto_first_child();
-            writer.push_str("#define ");
+    if ctx.config.sort_properties {
+        statements = sort_properties(statements, &ctx.config.property_order);
+    }
 
-            // Function and args
-            for _ in 0..2 {
-                cursor.goto_next_sibling();
-                writer.push_str(get_text(source, cursor));
-            }
-            writer.push(' ');
+    if ctx.config.sort_nodes == SortNodes::UnitAddress {
+        statements = sort_nodes(statements);
+    }
 
-            // Value
-            cursor.goto_next_sibling();
-            writer.push_str(get_text(source, cursor));
+    let columns = match ctx.config.align_trailing_comments {
+        true => align_columns(&statements, ctx),
+        false => vec![None; statements.len()],
+    };
 
-            writer.push('\n');
-            cursor.goto_parent();
+    let mut parts = header
+        .iter()
+        .map(|comment| trailing_comment(comment, None, source, ctx))
+        .collect::<Vec<_>>();
 
-            // Add a newline if this is the last preproc directive
-            if lookahead(cursor).is_some_and(|n| !is_preproc(&n)) {
-                writer.push('\n');
-            }
+    for (i, statement) in statements.iter().enumerate() {
+        if i > 0 || leading {
+            parts.push(Doc::HardLine);
         }
-        "preproc_ifdef" => {
-            print_indent(writer, ctx);
+        parts.extend((0..statement.blank_lines).map(|_| Doc::HardLine));
 
-            // #ifdef
-            cursor.goto_first_child();
-            writer.push_str(get_text(source, cursor).trim());
-            writer.push(' ');
+        parts.push(statement.doc.clone());
 
-            // Name
-            cursor.goto_next_sibling();
-            writer.push_str(get_text(source, cursor));
-            writer.push('\n');
+        for (j, comment) in statement.comments.iter().enumerate() {
+            let column = columns[i].filter(|_| j == 0);
+            parts.push(trailing_comment(comment, column, source, ctx));
+        }
+    }
 
-            // Body
-            while cursor.goto_next_sibling() {
-                traverse(writer, source, cursor, ctx);
-            }
+    concat(parts)
+}
 
-            // Closing
-            print_indent(writer, ctx);
-            writer.push_str("#endif\n");
-            cursor.goto_parent();
+/// The name of a property, without its labels.
+fn property_name<'a>(node: &Node, source: &'a String) -> &'a str {
+    node.child_by_field_name("name")
+        .or_else(|| node.child(0))
+        .map(|name| node_text(source, &name))
+        .unwrap_or_default()
+}
 
-            // Add a newline if this is the last preproc directive
-            if lookahead(cursor).is_some_and(|n| !is_preproc(&n)) {
-                writer.push('\n');
-            }
+/// The unit address of a node, such as `40002000` in `uart@40002000`, or of
+/// the first node with one in a conditional block.
+fn unit_address(node: &Node, source: &String) -> Option<Vec<u64>> {
+    let mut cursor = node.walk();
+    let mut children = node.children(&mut cursor);
+
+    match node.kind() {
+        "node" => children
+            .find(|n| n.kind() == "unit_address")
+            .and_then(|n| parse_unit_address(node_text(source, &n))),
+        "preproc_if" | "preproc_ifdef" => children
+            .filter(|n| n.kind() == "node")
+            .find_map(|n| unit_address(&n, source)),
+        _ => None,
+    }
+}
+
+/// A statement that can be moved along with the comments right above it, or
+/// a statement that stays in place.
+enum Unit<'a> {
+    Moved(Vec<Statement<'a>>),
+    Fixed(Statement<'a>),
+}
+
+/// Splits the statements into the ones that `movable` accepts, with the
+/// comments above them that aren't separated from them by a blank line, and
+/// the rest.
+fn units<'a>(
+    statements: Vec<Statement<'a>>,
+    movable: impl Fn(&Statement) -> bool,
+) -> Vec<Unit<'a>> {
+    let mut units = Vec::new();
+    let mut comments = Vec::new();
+
+    for statement in statements {
+        if statement.blank_lines > 0 {
+            units.extend(comments.drain(..).map(Unit::Fixed));
         }
-        "identifier" | "string_literal" | "unit_address" | "path" => {
-            writer.push_str(get_text(source, cursor));
+
+        if statement.comment {
+            comments.push(statement);
+        } else if movable(&statement) {
+            comments.push(statement);
+            units.push(Unit::Moved(mem::take(&mut comments)));
+        } else {
+            units.extend(comments.drain(..).map(Unit::Fixed));
+            units.push(Unit::Fixed(statement));
         }
-        "reference" => {
-            // A reference has a format of "&label" or "&{path}".
+    }
+
+    units.extend(comments.into_iter().map(Unit::Fixed));
+    units
+}
+
+/// Sorts the properties of a block within each group of properties that
+/// isn't separated by a blank line or another statement, so that groups of
+/// properties are kept. The comments right above a property and at the end
+/// of its line move with it.
+fn sort_properties<'a>(
+    statements: Vec<Statement<'a>>,
+    order: &[String],
+) -> Vec<Statement<'a>> {
+    let mut group: Vec<Vec<Statement>> = Vec::new();
+    let mut sorted = Vec::new();
+
+    let flush = |group: &mut Vec<Vec<Statement<'a>>>,
+                 sorted: &mut Vec<Statement<'a>>| {
+        // The blank lines before the group stay before it.
+        let blank_lines = group
+            .first_mut()
+            .map_or(0, |unit| mem::take(&mut unit[0].blank_lines));
+
+        group.sort_by_key(|unit| {
+            let name = unit.last().and_then(|s| s.property).unwrap_or_default();
+            property_key(name, order)
+        });
+
+        if let Some(unit) = group.first_mut() {
+            unit[0].blank_lines = blank_lines;
+        }
+        sorted.extend(group.drain(..).flatten());
+    };
 
-            // Visit all children of the reference without changing the
-            // indentation.
-            if cursor.goto_first_child() {
-                traverse(writer, source, cursor, ctx);
-                while cursor.goto_next_sibling() {
-                    traverse(writer, source, cursor, ctx);
+    for unit in units(statements, |s| s.property.is_some()) {
+        match unit {
+            Unit::Moved(unit) => {
+                if unit[0].blank_lines > 0 {
+                    flush(&mut group, &mut sorted);
                 }
-                cursor.goto_parent();
+
+                group.push(unit);
             }
-        }
-        // This is a general handler for any type that just needs to traverse
-        // its children.
-        "node" | "property" | "delete_node" | "delete_property" => {
-            // A node will typically have children in a format of:
-            // [<identifier>:] [&]<identifier> { [nodes and properties] }
-            cursor.goto_first_child();
-
-            // Nodes are preceded by a label or name identifier that need to be
-            // indented. We can check for this by seeing if any siblings are
-            // before us.
-            if cursor.node().prev_sibling().is_none() {
-                print_indent(writer, ctx);
+            Unit::Fixed(statement) => {
+                flush(&mut group, &mut sorted);
+                sorted.push(statement);
             }
+        }
+    }
 
-            // Increment the indentation for children and also check whether
-            // we've identified a node keymap node for Zephyr-specific keymaps.
-            let ctx = ctx.inc(1);
-            let ctx = match get_text(source, cursor) {
-                "keymap" => ctx.keymap(),
-                "bindings" => ctx.bindings(),
-                _ => ctx,
-            };
+    flush(&mut group, &mut sorted);
+    sorted
+}
 
-            loop {
-                traverse(writer, source, cursor, &ctx);
-                if !cursor.goto_next_sibling() {
-                    break;
-                }
+/// Sorts the nodes of a block that have a unit address by their address,
+/// along with the conditional blocks around them. Nodes are only sorted
+/// within runs of consecutive nodes with an address, so that they aren't
+/// moved across deletions, directives or other statements. The blank lines
+/// between statements stay in place, and the comments right above a node
+/// move with it.
+fn sort_nodes<'a>(statements: Vec<Statement<'a>>) -> Vec<Statement<'a>> {
+    let mut run: Vec<Vec<Statement>> = Vec::new();
+    let mut sorted = Vec::new();
+
+    let flush = |run: &mut Vec<Vec<Statement<'a>>>,
+                 sorted: &mut Vec<Statement<'a>>| {
+        // The blank lines before each node stay where they are.
+        let blank_lines = run
+            .iter_mut()
+            .map(|unit| mem::take(&mut unit[0].blank_lines))
+            .collect::<Vec<_>>();
+
+        run.sort_by_cached_key(|unit| {
+            unit.last().and_then(|s| s.address.clone())
+        });
+
+        for (mut unit, blank_lines) in run.drain(..).zip(blank_lines) {
+            unit[0].blank_lines = blank_lines;
+            sorted.extend(unit);
+        }
+    };
+
+    for unit in units(statements, |s| s.address.is_some()) {
+        match unit {
+            Unit::Moved(unit) => run.push(unit),
+            Unit::Fixed(statement) => {
+                flush(&mut run, &mut sorted);
+                sorted.push(statement);
             }
+        }
+    }
+
+    flush(&mut run, &mut sorted);
+    sorted
+}
+
+/// Finds the column to align the trailing comment of each statement to,
+/// which is one past the longest of the lines whose trailing comments are on
+/// consecutive lines in the source.
+fn align_columns(
+    statements: &[Statement],
+    ctx: &Context,
+) -> Vec<Option<usize>> {
+    let widths = statements
+        .iter()
+        .map(|statement| match statement.comments.is_empty() {
+            true => None,
+            false => line_width(&statement.doc, ctx),
+        })
+        .collect::<Vec<_>>();
+
+    let row = |i: usize| statements[i].comments[0].start_position().row;
+    let mut columns = vec![None; statements.len()];
+    let mut start = 0;
 
-            // Return to the "node"'s node element to continue traversal.
-            cursor.goto_parent();
+    while start < statements.len() {
+        let mut end = start + 1;
 
-            // Place a newline before node siblings if they follow a property.
-            if node.kind() == "property"
-                && lookahead(cursor).is_some_and(|n| n.kind() == "node")
+        if widths[start].is_some() {
+            while end < statements.len()
+                && widths[end].is_some()
+                && row(end - 1) + 1 == row(end)
             {
-                writer.push('\n');
+                end += 1;
             }
+
+            let column =
+                widths[start..end].iter().flatten().max().map(|w| w + 1);
+            columns[start..end].fill(column);
         }
-        "byte_string_literal" => {
-            let hex_string = get_text(source, cursor);
-            // Trim the [ and ] off of the source string we obtained.
-            let hex_bytes = hex_string[1..hex_string.len() - 1]
-                .split_whitespace()
-                .collect::<Vec<&str>>();
-            let hex_chunks = hex_bytes.chunks(16).collect::<Vec<&[&str]>>();
-
-            // For smaller byte chunks it reads better if we just one line
-            // everything, but for anything beyond 16 bytes we split it into
-            // multiple lines.
-            if hex_chunks.len() == 1 {
-                writer.push_str(&format!("[{}]", hex_chunks[0].join(" ")));
-            } else {
-                writer.push_str("[\n");
-                for (i, &line) in hex_chunks.iter().enumerate() {
-                    print_indent(writer, ctx);
-                    writer.push_str(&format!("{}\n", &line.join(" ")));
-                    if i == hex_chunks.len() - 1 {
-                        print_indent(writer, &ctx.dec(1));
-                        writer.push(']');
-                    }
-                }
+
+        start = end;
+    }
+
+    columns
+}
+
+/// The width of the statement when printed on a single line at the
+/// indentation of the context, or `None` if it spans several lines.
+fn line_width(doc: &Doc, ctx: &Context) -> Option<usize> {
+    let indent = text(ctx.config.indent_str.repeat(ctx.indent));
+    let line = doc::render(
+        &concat(vec![indent, doc.clone()]),
+        render_width(ctx.config),
+        &ctx.config.indent_str,
+    );
+
+    match line.contains('\n') {
+        true => None,
+        false => Some(line.chars().count()),
+    }
+}
+
+/// The width to render the output in, which is unlimited unless
+/// `max_width` is set.
+fn render_width(config: &Config) -> usize {
+    config.max_width.unwrap_or(usize::MAX)
+}
+
+/// Prints a comment at the end of the line, after a space or padded to the
+/// given column.
+fn trailing_comment(
+    node: &Node,
+    column: Option<usize>,
+    source: &String,
+    ctx: &Context,
+) -> Doc {
+    let padding = match column {
+        Some(column) => Doc::PadTo(column),
+        None => text(" "),
+    };
+
+    line_suffix(concat(vec![padding, print_comment(node, source, ctx)]))
+}
+
+/// Prints a node, property or deletion. The statements in the body of a node
+/// are indented on the lines between its braces, and the values of a
+/// property are broken across lines if they don't fit in the width.
+fn print_statement(node: &Node, source: &String, ctx: &Context) -> Doc {
+    // A node will typically have children in a format of:
+    // [<identifier>:] [&]<identifier> { [nodes and properties] }
+    let mut cursor = node.walk();
+    let children = node.children(&mut cursor).collect::<Vec<_>>();
+
+    // Increment the indentation for children and also check whether
+    // we've identified a node keymap node for Zephyr-specific keymaps.
+    let ctx = ctx.inc(1);
+    let ctx = match children.first().map(|n| node_text(source, n)) {
+        Some("keymap") => ctx.keymap(),
+        Some("bindings") => ctx.bindings(),
+        _ => ctx,
+    };
+
+    if node.kind() == "property" {
+        if let Some(doc) = print_values(&children, source, &ctx) {
+            return doc;
+        }
+    }
+
+    let mut parts = Vec::new();
+    let mut rest = children.as_slice();
+
+    while let Some((child, tail)) = rest.split_first() {
+        rest = tail;
+
+        match child.kind() {
+            "{" => {
+                // The body up to the closing brace
+                let end = tail
+                    .iter()
+                    .position(|n| n.kind() == "}")
+                    .unwrap_or(tail.len());
+
+                parts.push(text(" {"));
+                parts.push(indent(print_block(
+                    &tail[..end],
+                    source,
+                    &ctx,
+                    true,
+                )));
+                parts.push(Doc::HardLine);
+                rest = &tail[end..];
             }
+            "comment" => {
+                let comment = print_comment(child, source, &ctx);
+
+                // Comments between the parts of a statement stay at the end
+                // of their line, or get a line of their own.
+                parts.push(match is_trailing_comment(child, source) {
+                    true => line_suffix(concat(vec![text(" "), comment])),
+                    false => concat(vec![
+                        indent(concat(vec![Doc::HardLine, comment])),
+                        Doc::HardLine,
+                    ]),
+                });
+            }
+            _ => parts.push(traverse(child, source, &ctx)),
         }
+    }
 
-        "integer_cells" => {
-            cursor.goto_first_child();
+    concat(parts)
+}
 
-            // Keymap bindings are a special snowflake
-            if ctx.has_zephyr_syntax() {
-                print_bindings(writer, source, cursor, ctx);
-                return;
-            }
+/// Prints a property with its values filled into lines that fit in the
+/// width. The lines after the first continue under the first cell of a list
+/// of cells, or under the first value otherwise. Returns `None` for
+/// properties whose values can't be broken across lines this way, such as
+/// keymap bindings, byte strings and values with comments between them.
+fn print_values(
+    children: &[Node],
+    source: &String,
+    ctx: &Context,
+) -> Option<Doc> {
+    let unbreakable =
+        |n: &Node| matches!(n.kind(), "comment" | "byte_string_literal");
+    if ctx.has_zephyr_syntax() || children.iter().any(unbreakable) {
+        return None;
+    }
+
+    let equals = children.iter().position(|n| n.kind() == "=")?;
+    let (end, values) = children[equals + 1..].split_last()?;
+    if end.kind() != ";" || values.is_empty() {
+        return None;
+    }
 
-            writer.push('<');
-            let mut first = true;
+    let mut docs = Vec::new();
 
-            while cursor.goto_next_sibling() {
-                match cursor.node().kind() {
-                    ">" => break,
-                    _ => {
-                        if first {
-                            first = false;
-                        } else {
-                            writer.push(' ');
-                        }
+    // The values alternate with the commas between them.
+    for (i, value) in values.iter().enumerate() {
+        match i % 2 {
+            1 if value.kind() == "," => continue,
+            1 => return None,
+            _ if value.kind() == "," => return None,
+            _ => {}
+        }
 
-                        writer.push_str(get_text(source, cursor));
+        let separator = if i + 1 == values.len() { ";" } else { "," };
+        let doc = match value.kind() {
+            "integer_cells" => {
+                let cells = cells(value, source, ctx.config);
+                match cells.split_last() {
+                    None => text(format!("<>{}", separator)),
+                    Some((last, init)) => {
+                        let mut cells =
+                            init.iter().map(text).collect::<Vec<_>>();
+                        cells.push(text(format!("{}>{}", last, separator)));
+                        concat(vec![text("<"), hang(fill(cells))])
                     }
                 }
             }
+            _ => concat(vec![traverse(value, source, ctx), text(separator)]),
+        };
 
-            writer.push('>');
-            cursor.goto_parent();
-        }
-        // All the non-named grammatical tokens that are emitted but handled
-        // simply with some output structure.
-        "@" => {
-            writer.push('@');
-        }
-        "&" => {
-            writer.push('&');
-        }
-        "&{" => {
-            writer.push_str("&{");
-        }
-        "}" => {
-            print_indent(writer, &ctx.dec(1));
-            writer.push('}');
+        if doc.has_hard_line() {
+            return None;
         }
-        "{" => {
-            writer.push_str(" {\n");
-        }
-        ":" => {
-            writer.push_str(": ");
-        }
-        ";" => {
-            writer.push_str(";\n");
-        }
-        "," => {
-            writer.push_str(", ");
-        }
-        "=" => {
-            writer.push_str(" = ");
+
+        docs.push(doc);
+    }
+
+    let name = children[..equals]
+        .iter()
+        .map(|child| traverse(child, source, ctx))
+        .collect();
+
+    Some(concat(vec![concat(name), text(" = "), hang(fill(docs))]))
+}
+
+/// Prints a byte string on a single line if its bytes fit in the configured
+/// number of columns, or on the lines between its brackets otherwise.
+fn print_byte_string(node: &Node, source: &String, ctx: &Context) -> Doc {
+    let config = ctx.config;
+    let hex_string = node_text(source, node);
+    // Trim the [ and ] off of the source string we obtained.
+    let inner = &hex_string[1..hex_string.len() - 1];
+
+    let words = |bytes: &[String]| {
+        bytes
+            .chunks(config.byte_string_group.max(1))
+            .map(|word| word.concat())
+            .collect::<Vec<_>>()
+    };
+
+    let lines = match config.byte_string_columns {
+        ByteStringColumns::Count(columns) => {
+            split_bytes(inner, config.hex_case)
+                .chunks(columns.max(1))
+                .map(|line| words(line).join(" "))
+                .collect::<Vec<_>>()
         }
-        "/delete-node/" | "/delete-property/" => {
-            writer.push_str(&format!("{} ", kind));
+        ByteStringColumns::Layout(ByteStringLayout::Width) => {
+            let bytes = split_bytes(inner, config.hex_case);
+            let words = words(&bytes).into_iter().map(text).collect();
+
+            return group(concat(vec![
+                text("["),
+                indent(concat(vec![Doc::SoftLine, fill(words)])),
+                Doc::SoftLine,
+                text("]"),
+            ]));
         }
-        _ => {
-            if ctx.config.warn_on_unhandled_tokens {
-                eprintln!(
-                    "unhandled type '{}' ({} {}): {}",
-                    node.kind(),
-                    node.child_count(),
-                    if node.child_count() == 1 { "child" } else { "children" },
-                    get_text(source, cursor)
-                );
+        ByteStringColumns::Layout(ByteStringLayout::Preserve) => inner
+            .lines()
+            .map(|line| {
+                let words = line.split_whitespace();
+                let words =
+                    words.map(|w| format_hex_digits(w, config.hex_case));
+                words.collect::<Vec<_>>().join(" ")
+            })
+            .filter(|line| !line.is_empty())
+            .collect(),
+    };
+
+    if lines.len() <= 1 {
+        return text(format!("[{}]", lines.join(" ")));
+    }
+
+    let lines = lines
+        .into_iter()
+        .flat_map(|line| [Doc::HardLine, text(line)])
+        .collect();
+
+    concat(vec![text("["), indent(concat(lines)), Doc::HardLine, text("]")])
+}
+
+/// The text of each cell in a list of cells, without the angle brackets.
+/// Integers are written in the radix configured for the property and with
+/// the configured case of hexadecimal digits.
+fn cells(node: &Node, source: &String, config: &Config) -> Vec<String> {
+    let radix = node
+        .parent()
+        .filter(|n| n.kind() == "property")
+        .and_then(|n| config.radix.get(property_name(&n, source)))
+        .copied();
+
+    let mut cursor = node.walk();
+    node.children(&mut cursor)
+        .skip(1)
+        .take_while(|n| n.kind() != ">")
+        .map(|n| match n.kind() {
+            "integer_literal" => {
+                format_integer(node_text(source, &n), radix, config.hex_case)
             }
-            // Since we're unsure of this node just traverse its children
-            if cursor.goto_first_child() {
-                traverse(writer, source, cursor, ctx);
+            _ => node_text(source, &n).to_owned(),
+        })
+        .collect()
+}
+
+/// Normalizes the space after the `//` of a line comment. Block comments are
+/// kept as written.
+fn format_comment(comment: &str) -> String {
+    match comment.strip_prefix("//") {
+        Some(text) if text.trim().is_empty() => "//".to_owned(),
+        Some(text) => format!("// {}", text.trim()),
+        None => comment.to_owned(),
+    }
+}
 
-                while cursor.goto_next_sibling() {
-                    traverse(writer, source, cursor, &ctx.inc(1));
+/// Prints a comment, re-indenting the lines of a block comment to follow the
+/// new position of the comment.
+fn print_comment(node: &Node, source: &String, ctx: &Context) -> Doc {
+    let comment = node_text(source, node);
+
+    match comment.starts_with("/*") && comment.contains('\n') {
+        true => {
+            let column = node.start_position().column;
+            let gutters = ctx.config.normalize_comment_gutters;
+            reindent_block_comment(comment, column, gutters)
+        }
+        false => text(format_comment(comment)),
+    }
+}
+
+/// Moves the lines after the first of a block comment that started at
+/// `column` in the source by as much as the comment moved, so that diagrams
+/// inside the comment keep their shape. Lines are never moved to the left of
+/// where the comment now starts.
+fn reindent_block_comment(comment: &str, column: usize, gutters: bool) -> Doc {
+    let mut lines = comment.lines().map(str::trim_end);
+    let mut parts = vec![text(lines.next().unwrap_or_default())];
+    let lines = lines.collect::<Vec<_>>();
+
+    // Gutters are only normalized if every line has one.
+    let gutters = gutters
+        && lines
+            .iter()
+            .all(|line| line.is_empty() || line.trim_start().starts_with('*'));
+
+    let leading = |line: &&str| line.len() - line.trim_start().len();
+    let base = lines
+        .iter()
+        .filter(|line| !line.is_empty())
+        .map(leading)
+        .chain([column])
+        .min()
+        .unwrap_or_default();
+
+    for line in lines {
+        parts.push(Doc::HardLine);
+
+        if gutters {
+            parts.push(text(format!(
+                " {}",
+                match line.is_empty() {
+                    true => "*",
+                    false => line.trim_start(),
                 }
+            )));
+        } else if !line.is_empty() {
+            parts.push(text(&line[base..]));
+        }
+    }
+
+    hang(concat(parts))
+}
+
+/// Prints a run of line comments with their paragraphs reflowed to fit in
+/// `width` columns. Tables, diagrams and ignored runs are printed as written.
+fn print_comment_run(
+    run: &[Node],
+    width: usize,
+    source: &String,
+    ctx: &Context,
+) -> Doc {
+    let comments =
+        run.iter().map(|node| node_text(source, node)).collect::<Vec<_>>();
+
+    let indent = ctx.config.indent_str.chars().count() * ctx.indent;
+    let width = width.saturating_sub(indent + "// ".len());
+
+    let lines =
+        reflow_comments(&comments, width).into_iter().map(text).collect();
+
+    join(lines, Doc::HardLine)
+}
+
+/// Whether the node is a `//` comment on a line of its own.
+fn is_own_line_comment(node: &Node, source: &String) -> bool {
+    node.kind() == "comment"
+        && !is_trailing_comment(node, source)
+        && node_text(source, node).starts_with("//")
+}
+
+/// Finds the line comments on the lines right after the given one, or `None`
+/// if the comment continues the comments on the lines before it.
+fn comment_run<'a>(node: &Node<'a>, source: &String) -> Option<Vec<Node<'a>>> {
+    let follows = |prev: &Node, next: &Node| {
+        prev.end_position().row + 1 == next.start_position().row
+            && is_own_line_comment(prev, source)
+            && is_own_line_comment(next, source)
+    };
+
+    if node.prev_sibling().is_some_and(|prev| follows(&prev, node)) {
+        return None;
+    }
+
+    let mut run = vec![*node];
+    while let Some(next) = run
+        .last()
+        .and_then(Node::next_sibling)
+        .filter(|next| run.last().is_some_and(|prev| follows(prev, next)))
+    {
+        run.push(next);
+    }
+
+    Some(run)
+}
+
+/// Whether the comment starts on the line where the node before it ends, as
+/// in `compatible = "zmk,behavior-hold-tap"; // home row mod`.
+fn is_trailing_comment(node: &Node, source: &str) -> bool {
+    if node.kind() != "comment" {
+        return false;
+    }
+
+    // The line break that ends the condition of an `#if` is a node of its
+    // own.
+    let mut prev = node.prev_sibling();
+    while let Some(sibling) = prev.filter(|n| n.kind() == "\n") {
+        prev = sibling.prev_sibling();
+    }
+
+    prev.is_some_and(|prev| {
+        let end = content_end(&prev, source);
+        let breaks = source[end..prev.end_byte()].matches('\n').count();
+
+        prev.end_position().row - breaks == node.start_position().row
+    })
+}
+
+/// Prints a conditional directive and its body, followed by its `#elif` and
+/// `#else` branches. The bodies are printed at the same indentation as the
+/// directives.
+fn print_branches(node: &Node, source: &String, ctx: &Context) -> Doc {
+    let condition = match node.kind() {
+        "preproc_else" => None,
+        _ => node
+            .child_by_field_name("condition")
+            .or_else(|| node.child_by_field_name("name")),
+    };
+    let alternative = node.child_by_field_name("alternative");
+
+    let mut cursor = node.walk();
+    let children = node.children(&mut cursor).collect::<Vec<_>>();
+
+    // #if, #ifdef, #ifndef, #elif or #else
+    let mut line = children
+        .first()
+        .map(|n| directive(node_text(source, n)))
+        .unwrap_or_default();
+
+    if let Some(condition) = condition {
+        line.push(' ');
+        line.push_str(node_text(source, &condition));
+    }
+
+    // Body
+    let body = children
+        .iter()
+        .skip(1)
+        .filter(|&&child| {
+            child.is_named()
+                && Some(child) != condition
+                && Some(child) != alternative
+        })
+        .copied()
+        .collect::<Vec<_>>();
+
+    let mut parts = vec![text(line), print_block(&body, source, ctx, true)];
+
+    if let Some(alternative) = alternative {
+        parts.push(Doc::HardLine);
+        parts.push(print_branches(&alternative, source, ctx));
+    }
+
+    concat(parts)
+}
+
+/// Prints a #define with its value. Values that are continued over several
+/// lines are re-indented by their nesting of brackets, with the backslashes
+/// aligned to a common column.
+fn print_define(header: String, value: &str, ctx: &Context) -> String {
+    let Some(segments) = split_continuations(value) else {
+        return format!("{} {}", header, value);
+    };
+
+    // The body is indented below the #define unless it starts on the same
+    // line, where its brackets determine the indentation.
+    let base = match segments[0].is_empty() {
+        true => ctx.indent + 1,
+        false => ctx.indent,
+    };
+
+    let mut lines = vec![header];
+    let mut depth: usize = 0;
 
-                cursor.goto_parent();
+    for (i, segment) in segments.iter().enumerate() {
+        let (opened, closed, leading) = count_brackets(segment);
+
+        // The text before the first backslash stays on the #define line.
+        if i == 0 {
+            if !segment.is_empty() {
+                lines[0].push(' ');
+                lines[0].push_str(segment);
             }
+        } else if segment.is_empty() {
+            lines.push(String::new());
+        } else {
+            let indent = base + depth.saturating_sub(leading);
+            let indent = ctx.config.indent_str.repeat(indent);
+            lines.push(format!("{}{}", indent, segment));
         }
+
+        depth = (depth + opened).saturating_sub(closed);
+    }
+
+    // The first line is printed after the indentation of the directive.
+    let indent_width = ctx.config.indent_str.chars().count() * ctx.indent;
+    let width = |i: usize, line: &String| match i {
+        0 => line.chars().count() + indent_width,
+        _ => line.chars().count(),
     };
+    let column = lines
+        .iter()
+        .enumerate()
+        .take(lines.len() - 1)
+        .map(|(i, line)| width(i, line))
+        .max()
+        .unwrap_or(0)
+        + 1;
+
+    let mut output = String::new();
+    let last = lines.len() - 1;
+    for (i, line) in lines.iter().enumerate() {
+        output.push_str(line);
+
+        if i < last {
+            output.push_str(&" ".repeat(column - width(i, line)));
+            output.push_str("\\\n");
+        }
+    }
+
+    output
 }
 
+/// Splits the value of a #define into the text of each line continued with a
+/// backslash, or `None` if the value is a single line. Values where a string
+/// or comment spans several lines are also left as written, since changing
+/// the indentation would change their contents.
+fn split_continuations(value: &str) -> Option<Vec<&str>> {
+    let lines = value.split('\n').collect::<Vec<_>>();
+    if lines.len() < 2 {
+        return None;
+    }
+
+    let mut segments = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.trim_end_matches('\r');
+        let line = match i == lines.len() - 1 {
+            true => line,
+            false => line.strip_suffix('\\')?,
+        };
+
+        segments.push(line.trim());
+    }
+
+    let spans_lines = |segment: &&str| {
+        !segment.matches('"').count().is_multiple_of(2)
+            || segment.rfind("/*") > segment.rfind("*/")
+    };
+    if segments.iter().any(spans_lines) {
+        return None;
+    }
+
+    Some(segments)
+}
+
+/// Counts the brackets a line of a macro opens and closes, and how many of
+/// the closing brackets are at the start of the line.
+fn count_brackets(line: &str) -> (usize, usize, usize) {
+    let (mut opened, mut closed) = (0, 0);
+    let mut in_string = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => in_string = !in_string,
+            '{' | '(' | '[' if !in_string => opened += 1,
+            '}' | ')' | ']' if !in_string => closed += 1,
+            _ => {}
+        }
+    }
+
+    let leading = line
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .take_while(|c| matches!(c, '}' | ')' | ']'))
+        .count();
+
+    (opened, closed, leading)
+}
+
+fn node_text<'a>(source: &'a String, node: &Node) -> &'a str {
+    node.utf8_text(source.as_bytes()).unwrap_or("").trim()
+}
+
+/// Collects the bindings of a keymap layer, grouped into rows by the line
+/// each binding starts on in the source.
 fn collect_bindings(
     cursor: &mut TreeCursor,
     source: &String,
-    ctx: &Context,
-) -> VecDeque<String> {
-    let mut buf: VecDeque<String> = VecDeque::new();
+) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
     let mut item = String::new();
+    let mut line = 0;
 
     while cursor.goto_next_sibling() {
         match cursor.node().kind() {
@@ -347,10 +1063,19 @@ fn collect_bindings(
             _ => {
                 let text = get_text(source, cursor).trim();
 
-                // If this is a new binding, add a new item to the buffer
-                if !item.is_empty() && text.starts_with("&") {
-                    buf.push_back(item);
-                    item = String::new();
+                // If this is a new binding, add the previous one to the row
+                if item.is_empty() || text.starts_with("&") {
+                    if !item.is_empty() {
+                        row.push(mem::take(&mut item));
+                    }
+
+                    // Bindings that start on a new line start a new row
+                    let start = cursor.node().start_position().row;
+                    if !row.is_empty() && start != line {
+                        rows.push(mem::take(&mut row));
+                    }
+
+                    line = start;
                 }
 
                 // Add a space between each piece of text
@@ -365,96 +1090,442 @@ fn collect_bindings(
     }
 
     // Add the last item to the buffer
-    buf.push_back(item);
+    if !item.is_empty() {
+        row.push(item);
+    }
 
-    // Move the items from the temporary buffer into a new vector that contains
-    // the empty key spaces.
-    layouts::get_layout(&ctx.config.layout)
-        .bindings
-        .iter()
-        .map(|is_key| match is_key {
-            1 => buf.pop_front().unwrap_or_default(),
-            _ => String::new(),
-        })
-        .collect()
+    if !row.is_empty() {
+        rows.push(row);
+    }
+
+    rows
+}
+
+/// Collects the rows of every keymap layer in the tree, so that rows that are
+/// preserved as written can be aligned across layers.
+fn collect_keymap_rows(
+    node: &Node,
+    source: &String,
+    config: &Config,
+    keymap: bool,
+    bindings: bool,
+    rows: &mut Vec<Vec<String>>,
+) {
+    // Layers generated by macros are aligned with the other layers.
+    if let Some(call) = MacroCall::parse(node_text(source, node), config) {
+        if call.config.kind == MacroKind::Bindings {
+            rows.extend(bindings_rows(call.body).unwrap_or_default());
+        }
+
+        return;
+    }
+
+    // The cells are collected wherever the printer prints them as bindings,
+    // which is under both a `keymap` and a `bindings` node or property.
+    let mut cursor = node.walk();
+    if node.kind() == "integer_cells" {
+        if keymap && bindings && cursor.goto_first_child() {
+            rows.extend(collect_bindings(&mut cursor, source));
+        }
+
+        return;
+    }
+
+    let name = match node.kind() {
+        "node" | "property" => node.child(0).map(|n| node_text(source, &n)),
+        _ => None,
+    };
+
+    let keymap = keymap || name == Some("keymap");
+    let bindings = bindings || name == Some("bindings");
+
+    for child in node.children(&mut cursor) {
+        collect_keymap_rows(&child, source, config, keymap, bindings, rows);
+    }
+}
+
+/// The column sizes of rows that are kept as written. Rows with more columns
+/// than were collected across the layers are sized on their own.
+fn preserved_sizes(rows: &[Vec<String>], ctx: &Context) -> Vec<usize> {
+    match rows.iter().all(|row| row.len() <= ctx.column_sizes.len()) {
+        true => ctx.column_sizes.to_vec(),
+        false => calculate_sizes(rows),
+    }
 }
 
 /// Calculate the maximum size of each column in the bindings table.
-fn calculate_sizes(buf: &VecDeque<String>, row_size: usize) -> Vec<usize> {
+fn calculate_sizes(rows: &[Vec<String>]) -> Vec<usize> {
     let mut sizes = Vec::new();
 
-    for i in 0..row_size {
-        let mut max = 0;
+    for row in rows {
+        for (col, item) in row.iter().enumerate() {
+            if col == sizes.len() {
+                sizes.push(0);
+            }
+
+            sizes[col] = sizes[col].max(item.len());
+        }
+    }
 
-        for j in (i..buf.len()).step_by(row_size) {
-            let len = buf[j].len();
+    sizes
+}
 
-            if len > max {
-                max = len;
-            }
+fn print_bindings(node: &Node, source: &String, ctx: &Context) -> Doc {
+    let mut cursor = node.walk();
+    cursor.goto_first_child();
+    let rows = collect_bindings(&mut cursor, source);
+
+    let layout = match ctx.layout {
+        Ok(Some(layout)) => layout,
+        Ok(None) => {
+            // Keep the rows as written, aligned with the other layers.
+            return match rows.is_empty() {
+                true => text("<>"),
+                false => print_rows(&rows, &preserved_sizes(&rows, ctx)),
+            };
+        }
+        Err(err) => {
+            let property = node.parent().unwrap_or(*node);
+            ctx.report(Diagnostic::at_node(source, &property, err.clone()));
+            return text(node_text(source, node));
         }
+    };
 
-        sizes.push(max);
+    let bindings = rows.into_iter().flatten().collect::<Vec<_>>();
+
+    // Laying out the wrong number of bindings would either drop bindings or pad
+    // the layer with empty keys, so we leave the bindings untouched instead.
+    if bindings.len() != layout.key_count() {
+        let property = node.parent().unwrap_or(*node);
+
+        // The layer is the node containing the bindings property, which we name
+        // by its header (e.g. "base: default_layer").
+        let layer = property
+            .parent()
+            .map(|n| node_text(source, &n))
+            .and_then(|text| text.split('{').next())
+            .unwrap_or_default()
+            .trim();
+
+        let count = bindings.len();
+        report_binding_mismatch(&property, layer, source, count, layout, ctx);
+        return text(node_text(source, node));
     }
 
-    sizes
+    // Each binding is placed at the position of its key, with empty cells for
+    // the gaps and gutters between keys.
+    let rows = layout.table(bindings);
+    print_rows(&rows, &calculate_sizes(&rows))
 }
 
-fn print_bindings(
-    writer: &mut String,
-    source: &String,
-    cursor: &mut TreeCursor,
+/// Prints the bindings as a table with the given column sizes.
+fn print_rows(rows: &[Vec<String>], sizes: &[usize]) -> Doc {
+    concat(vec![
+        text("<"),
+        indent(print_table(rows, sizes)),
+        Doc::HardLine,
+        text(">"),
+    ])
+}
+
+/// Prints each row of bindings on its own line, padding each binding to the
+/// size of its column.
+fn print_table(rows: &[Vec<String>], sizes: &[usize]) -> Doc {
+    let mut parts = Vec::new();
+
+    for row in rows {
+        let mut line = String::new();
+
+        for (col, item) in row.iter().enumerate() {
+            // Don't add padding to the last binding in the row
+            let padding = match col == row.len() - 1 {
+                true => 0,
+                false => sizes[col] + 3,
+            };
+
+            line.push_str(&pad_right(item, padding));
+        }
+
+        parts.push(Doc::HardLine);
+        parts.push(text(line));
+    }
+
+    concat(parts)
+}
+
+fn report_binding_mismatch(
+    node: &Node,
+    layer: &str,
+    source: &str,
+    count: usize,
+    layout: &layouts::KeyboardLayout,
     ctx: &Context,
 ) {
-    cursor.goto_first_child();
-    writer.push('<');
+    let mut message = format!(
+        "layer `{}` has {} bindings but the layout has {} keys",
+        layer,
+        count,
+        layout.key_count()
+    );
+
+    let severity = match ctx.config.binding_mismatch {
+        BindingMismatch::Verbatim => {
+            message.push_str(", leaving its bindings unformatted");
+            Severity::Warning
+        }
+        BindingMismatch::Error => Severity::Error,
+    };
 
-    let buf = collect_bindings(cursor, source, ctx);
-    let row_size = layouts::get_layout(&ctx.config.layout).row_size();
-    let sizes = calculate_sizes(&buf, row_size);
+    ctx.report(
+        Diagnostic::at_node(source, node, message).with_severity(severity),
+    );
+}
 
-    buf.iter().enumerate().for_each(|(i, item)| {
-        let col = i % row_size;
+/// Prints a call of a macro that generates nodes, such as
+/// `ZMK_LAYER(base, ...)`, with its properties or bindings indented on the
+/// lines after the call. Returns `None` if the node isn't such a call.
+fn print_macro_call(
+    node: &Node,
+    source: &String,
+    ctx: &Context,
+) -> Option<Doc> {
+    let call_text = node_text(source, node);
+    let call = MacroCall::parse(call_text, ctx.config)?;
+    let semicolon = if call.semicolon { ";" } else { "" };
+
+    if call.body.is_empty() {
+        return Some(text(format!(
+            "{}({}){}",
+            call.name,
+            call.args.join(", "),
+            semicolon
+        )));
+    }
 
-        // Add a newline at the start of each row
-        if col == 0 {
-            writer.push('\n');
-            print_indent(writer, ctx);
+    let body = match call.config.kind {
+        MacroKind::Properties => {
+            format_macro_properties(call.body, source, ctx)
         }
+        MacroKind::Bindings => format_macro_bindings(&call, node, source, ctx),
+    };
 
-        // Don't add padding to the last binding in the row
-        let padding = match (i + 1) % row_size == 0 {
-            true => 0,
-            false => sizes[col] + 3,
+    // Calls we can't format are kept as written rather than mangled.
+    let Some(body) = body else {
+        return Some(text(call_text));
+    };
+
+    let comma = if call.args.is_empty() { "" } else { "," };
+
+    Some(concat(vec![
+        text(format!("{}({}{}", call.name, call.args.join(", "), comma)),
+        indent(body),
+        Doc::HardLine,
+        text(format!("){}", semicolon)),
+    ]))
+}
+
+/// Formats the properties and nodes passed to a macro by formatting them as
+/// the body of a node. Returns `None` if they don't parse as one.
+fn format_macro_properties(
+    body: &str,
+    source: &str,
+    ctx: &Context,
+) -> Option<Doc> {
+    let body_source = format!("__macro__ {{\n{}\n}};\n", body);
+    let tree = parse(body_source.clone());
+    if tree.root_node().has_error() {
+        return None;
+    }
+
+    let node = tree.root_node().child(0)?;
+    let mut cursor = node.walk();
+    let children = node.children(&mut cursor).collect::<Vec<_>>();
+
+    // The statements between the braces that open and close the node.
+    let open = children.iter().position(|n| n.kind() == "{")?;
+    let close = children.iter().rposition(|n| n.kind() == "}")?;
+    let children = children.get(open + 1..close)?;
+
+    // The statements are kept in their order, since the tokens of the call
+    // can't be verified if they were moved.
+    let config = Config {
+        sort_properties: false,
+        sort_nodes: SortNodes::Preserve,
+        ..ctx.config.clone()
+    };
+    let diagnostics = RefCell::new(Vec::new());
+    let body_ctx =
+        Context { config: &config, diagnostics: &diagnostics, ..ctx.inc(1) };
+
+    let doc = print_block(children, &body_source, &body_ctx, true);
+
+    // Diagnostics point into the source made up for the body, so they are
+    // moved to where the body is in the file.
+    let (body_line, body_column) = position_in(source, body);
+    for diagnostic in diagnostics.into_inner() {
+        // The body starts on the second line of its source.
+        let (line, column) = match diagnostic.line {
+            2 => (body_line, body_column + diagnostic.column - 1),
+            line => (body_line + line.saturating_sub(2), diagnostic.column),
         };
 
-        writer.push_str(&pad_right(item, padding));
-    });
+        ctx.report(
+            Diagnostic::new(source, line, column, diagnostic.message)
+                .with_severity(diagnostic.severity),
+        );
+    }
+
+    Some(doc)
+}
 
-    // Close the bindings
-    writer.push('\n');
-    print_indent(writer, &ctx.dec(1));
-    writer.push('>');
+/// The 1-based line and column where `text`, which is a slice of `source`,
+/// starts in `source`.
+fn position_in(source: &str, text: &str) -> (usize, usize) {
+    let offset = (text.as_ptr() as usize)
+        .saturating_sub(source.as_ptr() as usize)
+        .min(source.len());
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
 
-    cursor.goto_parent();
+    (before.matches('\n').count() + 1, offset - line_start + 1)
 }
 
-pub fn print(source: &String, config: &Config) -> String {
-    let mut writer = String::new();
-    let tree = parse(source.clone());
-    let mut cursor = tree.walk();
+/// Formats the bindings passed to a layer macro as a table, like the bindings
+/// of a keymap layer. Returns `None` if they should be kept as written.
+fn format_macro_bindings(
+    call: &MacroCall,
+    node: &Node,
+    source: &str,
+    ctx: &Context,
+) -> Option<Doc> {
+    let rows = bindings_rows(call.body)?;
+
+    let (rows, sizes) = match ctx.layout {
+        Ok(Some(layout)) => {
+            let bindings = rows.into_iter().flatten().collect::<Vec<_>>();
+
+            if bindings.len() != layout.key_count() {
+                let layer = call.args.first().copied().unwrap_or(call.name);
+                let count = bindings.len();
+                report_binding_mismatch(
+                    node, layer, source, count, layout, ctx,
+                );
+                return None;
+            }
 
-    let ctx =
-        Context { indent: 0, bindings: false, keymap: false, config: config };
+            let rows = layout.table(bindings);
+            let sizes = calculate_sizes(&rows);
+            (rows, sizes)
+        }
+        Ok(None) => {
+            let sizes = preserved_sizes(&rows, ctx);
+            (rows, sizes)
+        }
+        Err(err) => {
+            ctx.report(Diagnostic::at_node(source, node, err.clone()));
+            return None;
+        }
+    };
 
-    // The first node is the root document node, so we have to traverse all it's
-    // children with the same indentation level.
-    cursor.goto_first_child();
-    traverse(&mut writer, source, &mut cursor, &ctx);
+    Some(print_table(&rows, &sizes))
+}
 
-    while cursor.goto_next_sibling() {
-        traverse(&mut writer, source, &mut cursor, &ctx);
+/// The result of successfully formatting a file.
+#[derive(Debug)]
+pub struct Formatted {
+    pub text: String,
+    /// Warnings about parts of the file that were left unformatted.
+    pub diagnostics: Vec<Diagnostic>,
+    /// The layout picked for the file when the layout is `auto`.
+    pub detected_layout: Option<Detection>,
+}
+
+/// Formats the given source code. Files that fail to parse are not formatted
+/// and instead return a diagnostic for each syntax error. Unless disabled, the
+/// output is also verified to contain the same tokens as the input.
+pub fn print(
+    source: &String,
+    config: &Config,
+) -> Result<Formatted, Vec<Diagnostic>> {
+    print_file(source, config, None)
+}
+
+/// Formats the source code of a file. The path of the file is used to resolve
+/// its includes when deriving the keyboard layout from the file. Options set
+/// by `// dtsfmt:` comments in the file take precedence over the config.
+pub fn print_file(
+    source: &String,
+    config: &Config,
+    path: Option<&Path>,
+) -> Result<Formatted, Vec<Diagnostic>> {
+    let document = Document::new(source, path);
+    let tree = &document.tree;
+
+    // Printing a tree with errors in it would silently drop or reshuffle the
+    // broken parts of the file, so we refuse to format it at all.
+    let errors = syntax_errors(tree, source);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let config = &match pragma::apply_pragmas(config, tree, source) {
+        Ok(config) => config,
+        Err(err) => return Err(vec![err]),
+    };
+
+    let detected_layout = config.detect_layout(&document);
+    let layout = config.keyboard_layout(&document, detected_layout.as_ref());
+
+    // Rows that are preserved as written are aligned across all layers.
+    let mut column_sizes = Vec::new();
+    if let Ok(None) = layout {
+        let mut rows = Vec::new();
+        collect_keymap_rows(
+            &tree.root_node(),
+            source,
+            config,
+            false,
+            false,
+            &mut rows,
+        );
+        column_sizes = calculate_sizes(&rows);
+    }
+
+    let diagnostics = RefCell::new(Vec::new());
+    let ctx = Context {
+        indent: 0,
+        bindings: false,
+        keymap: false,
+        config,
+        layout: &layout,
+        column_sizes: &column_sizes,
+        diagnostics: &diagnostics,
+    };
+
+    // The children of the root document node are printed with the same
+    // indentation level.
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    let children = root.children(&mut cursor).collect::<Vec<_>>();
+
+    let mut doc = print_block(&children, source, &ctx, false);
+    if !children.is_empty() {
+        doc = concat(vec![doc, Doc::HardLine]);
+    }
+
+    let writer = doc::render(&doc, render_width(config), &config.indent_str);
+
+    let mut diagnostics = diagnostics.into_inner();
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return Err(diagnostics);
+    }
+
+    if config.verify_tokens {
+        if let Err(err) = tokens::verify(tree, source, &writer, config) {
+            diagnostics.push(err);
+            return Err(diagnostics);
+        }
     }
 
-    writer
+    Ok(Formatted { text: writer, diagnostics, detected_layout })
 }
@@ -0,0 +1,92 @@
+/// The properties that come first in a node, in the order of the Linux
+/// kernel's devicetree coding style.
+const LEADING_PROPERTIES: [&str; 3] = ["compatible", "reg", "ranges"];
+
+/// The key to sort a property by, following the Linux kernel's devicetree
+/// coding style. `compatible`, `reg`, `ranges` and the `#*-cells` properties
+/// come first, followed by the properties in `order`, the rest alphabetically
+/// with vendor properties like `zmk,matrix-transform` after the standard ones,
+/// and `status` last.
+pub fn property_key<'a>(
+    name: &'a str,
+    order: &[String],
+) -> (usize, usize, &'a str) {
+    if let Some(i) = LEADING_PROPERTIES.iter().position(|&p| p == name) {
+        return (0, i, "");
+    }
+
+    // The cells keep the order they were written in.
+    if name.starts_with('#') && name.ends_with("-cells") {
+        return (1, 0, "");
+    }
+
+    if name == "status" {
+        return (4, 0, "");
+    }
+
+    match order.iter().position(|p| p == name) {
+        Some(i) => (2, i, ""),
+        None => (3, usize::from(name.contains(',')), name),
+    }
+}
+
+/// Parses a unit address such as `40002000` or `1,0` into the hexadecimal
+/// number of each of its parts, or `None` if it isn't numeric.
+pub fn parse_unit_address(address: &str) -> Option<Vec<u64>> {
+    address
+        .split(',')
+        .map(|part| {
+            let part = part.trim();
+            let digits = part.strip_prefix("0x").unwrap_or(part);
+            u64::from_str_radix(digits, 16).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_orders_properties() {
+        let order = vec!["label".to_owned(), "flavor".to_owned()];
+        let mut names = vec![
+            "tapping-term-ms",
+            "status",
+            "flavor",
+            "#size-cells",
+            "bindings",
+            "reg",
+            "#address-cells",
+            "label",
+            "compatible",
+            "zmk,matrix-transform",
+        ];
+
+        names.sort_by_key(|name| property_key(name, &order));
+
+        assert_eq!(
+            names,
+            vec![
+                "compatible",
+                "reg",
+                "#size-cells",
+                "#address-cells",
+                "label",
+                "flavor",
+                "bindings",
+                "tapping-term-ms",
+                "zmk,matrix-transform",
+                "status",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_parses_unit_addresses() {
+        assert_eq!(parse_unit_address("40002000"), Some(vec![0x40002000]));
+        assert_eq!(parse_unit_address("1,0x20"), Some(vec![1, 0x20]));
+        assert_eq!(parse_unit_address("ADDRESS"), None);
+        assert!(parse_unit_address("9") < parse_unit_address("a"));
+    }
+}
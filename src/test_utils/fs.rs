@@ -44,8 +44,11 @@ pub fn get_files_in_dir_recursive(path: &Path) -> Vec<(PathBuf, String)> {
         for entry in dir_path.read_dir().expect("read dir failed").flatten() {
             let entry_path = entry.path();
             if entry_path.is_file() {
-                let text = fs::read_to_string(&entry_path).unwrap();
-                result.push((entry_path, text));
+                // Other files next to the specs hold their config
+                if entry_path.extension().is_some_and(|ext| ext == "txt") {
+                    let text = fs::read_to_string(&entry_path).unwrap();
+                    result.push((entry_path, text));
+                }
             } else {
                 result.extend(read_dir_recursively(&entry_path));
             }
@@ -1,4 +1,5 @@
 use std::fmt::Display;
+use std::fs;
 use std::path::Path;
 
 use console::Style;
@@ -6,7 +7,6 @@ use similar::{ChangeTag, TextDiff};
 
 use super::get_specs_in_dir;
 use crate::config::Config;
-use crate::layouts::KeyboardLayoutType;
 use crate::printer::print;
 
 struct FailedTestResult {
@@ -45,14 +45,29 @@ impl Display for DiffFailedMessage<'_> {
     }
 }
 
+/// Specs use the config in a TOML file next to the spec file with the same
+/// name, e.g. `preserve.toml` for `preserve.txt`, if there is one.
+fn get_spec_config(spec_path: &Path) -> Config {
+    match fs::read_to_string(spec_path.with_extension("toml")) {
+        Ok(text) => toml::from_str(&text).expect("Failed to parse spec config"),
+        Err(_) => Config::builder().layout("kinesis:adv360").build(),
+    }
+}
+
 pub fn run_specs(directory_path: &Path) {
     let specs = get_specs_in_dir(directory_path);
     let test_count = specs.len();
     let mut failed_tests = Vec::new();
-    let config = Config::builder().layout(KeyboardLayoutType::Adv360).build();
 
-    for (_, spec) in specs {
-        let result = print(&spec.file_text, &config);
+    for (path, spec) in specs {
+        let config = get_spec_config(&path);
+        let result = match print(&spec.file_text, &config) {
+            Ok(result) => result.text,
+            Err(diagnostics) => diagnostics
+                .iter()
+                .map(|diagnostic| format!("{}\n", diagnostic))
+                .collect(),
+        };
 
         if result != spec.expected_text {
             failed_tests.push(FailedTestResult {
@@ -0,0 +1,248 @@
+use tree_sitter::{Node, Point, Tree};
+
+use crate::config::{Config, HexCase, SortNodes};
+use crate::diagnostics::Diagnostic;
+use crate::literals::{format_path, integer_value};
+use crate::parser::{parse, syntax_errors};
+use crate::sort::parse_unit_address;
+
+/// Node kinds whose text is compared verbatim since whitespace inside of them
+/// is significant.
+const VERBATIM_KINDS: [&str; 3] =
+    ["string_literal", "char_literal", "system_lib_string"];
+
+/// A token that affects the meaning of the file, i.e. anything that isn't
+/// whitespace or a comment.
+struct Token {
+    text: String,
+    position: Point,
+}
+
+fn collect_tokens(
+    node: &Node,
+    source: &str,
+    config: &Config,
+    tokens: &mut Vec<Token>,
+) {
+    if node.kind() == "comment" {
+        return;
+    }
+
+    let text = node.utf8_text(source.as_bytes()).unwrap_or("");
+
+    if VERBATIM_KINDS.contains(&node.kind()) {
+        tokens.push(Token {
+            text: text.to_owned(),
+            position: node.start_position(),
+        });
+        return;
+    }
+
+    // Numbers are compared by their value, since the case of their digits
+    // and their radix may have been changed.
+    let number = match node.kind() {
+        "integer_literal" => integer_value(text.trim()).map(|v| v.to_string()),
+        "unit_address" | "byte_string_literal" => Some(
+            text.split_whitespace().collect::<String>().to_ascii_lowercase(),
+        ),
+        "path" => Some(format_path(text.trim(), HexCase::Lower)),
+        _ => None,
+    };
+
+    if let Some(text) = number {
+        tokens.push(Token { text, position: node.start_position() });
+        return;
+    }
+
+    if node.child_count() == 0 {
+        // Whitespace is never significant outside of strings, so we strip it
+        // entirely. This also drops the newline tokens that end preprocessor
+        // directives.
+        let text = text.split_whitespace().collect::<String>();
+        if !text.is_empty() {
+            tokens.push(Token { text, position: node.start_position() });
+        }
+        return;
+    }
+
+    // The statements that may have been moved among the run of statements
+    // around them are compared in the order of their text.
+    let mut run = Vec::new();
+    let mut run_kind = None;
+
+    let flush = |run: &mut Vec<Vec<Token>>, tokens: &mut Vec<Token>| {
+        run.sort_by_cached_key(|statement| {
+            statement.iter().map(|token| token.text.clone()).collect::<Vec<_>>()
+        });
+        tokens.extend(run.drain(..).flatten());
+    };
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if child.kind() == "comment" {
+            continue;
+        }
+
+        let kind = movable_kind(&child, source, config);
+        if kind.is_none() || kind != run_kind {
+            flush(&mut run, tokens);
+        }
+        run_kind = kind;
+
+        if kind.is_some() {
+            let mut statement = Vec::new();
+            collect_tokens(&child, source, config, &mut statement);
+            run.push(statement);
+            continue;
+        }
+
+        collect_tokens(&child, source, config, tokens);
+    }
+
+    flush(&mut run, tokens);
+}
+
+/// The kind of sorting that may have moved the statement among its siblings,
+/// if any. Properties are sorted among the properties around them, and nodes
+/// with a unit address among the nodes with an address around them.
+fn movable_kind(
+    node: &Node,
+    source: &str,
+    config: &Config,
+) -> Option<&'static str> {
+    match node.kind() {
+        "property" if config.sort_properties => Some("property"),
+        "node" | "preproc_if" | "preproc_ifdef"
+            if config.sort_nodes == SortNodes::UnitAddress
+                && has_unit_address(node, source) =>
+        {
+            Some("node")
+        }
+        _ => None,
+    }
+}
+
+/// Whether the node, or any node in a conditional block, has a numeric unit
+/// address.
+fn has_unit_address(node: &Node, source: &str) -> bool {
+    let mut cursor = node.walk();
+    let mut children = node.children(&mut cursor);
+
+    match node.kind() {
+        "node" => {
+            children.find(|n| n.kind() == "unit_address").is_some_and(|n| {
+                let text = n.utf8_text(source.as_bytes()).unwrap_or("");
+                parse_unit_address(text.trim()).is_some()
+            })
+        }
+        _ => children
+            .filter(|n| n.kind() == "node")
+            .any(|n| has_unit_address(&n, source)),
+    }
+}
+
+fn significant_tokens(
+    tree: &Tree,
+    source: &str,
+    config: &Config,
+) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    collect_tokens(&tree.root_node(), source, config, &mut tokens);
+    tokens
+}
+
+/// Re-parses the formatted output and verifies that it contains the same
+/// significant tokens as the input. This guards against formatter bugs that
+/// would otherwise silently drop or change parts of a file. Properties and
+/// nodes that were sorted are compared regardless of their order, and numbers
+/// by their value.
+pub fn verify(
+    input: &Tree,
+    source: &str,
+    output: &str,
+    config: &Config,
+) -> Result<(), Diagnostic> {
+    let output_tree = parse(output.to_owned());
+
+    let expected = significant_tokens(input, source, config);
+    let actual = significant_tokens(&output_tree, output, config);
+
+    // Diagnostics point into the input file, so problems found in the output
+    // are reported at the last input token that was printed correctly.
+    let at_last_match = |matched: usize, message: String| {
+        let position = match matched.checked_sub(1) {
+            Some(i) => expected[i].position,
+            None => Point::default(),
+        };
+
+        Diagnostic::new(source, position.row + 1, position.column + 1, message)
+    };
+
+    if let Some(error) = syntax_errors(&output_tree, output).into_iter().next()
+    {
+        let matched = expected
+            .iter()
+            .zip(&actual)
+            .take_while(|(token, other)| {
+                let position =
+                    (other.position.row + 1, other.position.column + 1);
+                token.text == other.text
+                    && position < (error.line, error.column)
+            })
+            .count();
+
+        return Err(at_last_match(
+            matched,
+            format!(
+                "formatter bug: the formatted output failed to parse at {}:{} \
+                 ({})",
+                error.line, error.column, error.message
+            ),
+        ));
+    }
+
+    for (i, token) in expected.iter().enumerate() {
+        let Some(other) = actual.get(i) else {
+            return Err(Diagnostic::new(
+                source,
+                token.position.row + 1,
+                token.position.column + 1,
+                format!(
+                    "formatter bug: `{}` is missing from the formatted output",
+                    token.text
+                ),
+            ));
+        };
+
+        if token.text != other.text {
+            return Err(Diagnostic::new(
+                source,
+                token.position.row + 1,
+                token.position.column + 1,
+                format!(
+                    "formatter bug: expected `{}` but the formatted output has \
+                     `{}` at {}:{}",
+                    token.text,
+                    other.text,
+                    other.position.row + 1,
+                    other.position.column + 1
+                ),
+            ));
+        }
+    }
+
+    if let Some(extra) = actual.get(expected.len()) {
+        return Err(at_last_match(
+            expected.len(),
+            format!(
+                "formatter bug: the formatted output has an unexpected `{}` at \
+                 {}:{}",
+                extra.text,
+                extra.position.row + 1,
+                extra.position.column + 1
+            ),
+        ));
+    }
+
+    Ok(())
+}
@@ -1,8 +1,98 @@
 use std::path::PathBuf;
 
+use dtsfmt::config::{BindingMismatch, Config};
+use dtsfmt::printer::{print, print_file};
 use dtsfmt::test_utils::run_specs;
 
 #[test]
 fn test_specs() {
     run_specs(&PathBuf::from("./tests/specs"));
 }
+
+#[test]
+fn test_syntax_errors() {
+    let config = Config::default();
+
+    for source in ["/ {\n  foo = <1 2>\n};\n", "/ {\n  node {\n    foo;\n};\n"]
+    {
+        let diagnostics = print(&source.to_owned(), &config).unwrap_err();
+        assert!(!diagnostics.is_empty());
+    }
+}
+
+#[test]
+fn test_binding_mismatch() {
+    let source =
+        "/ {\n  keymap {\n    layer {\n      bindings = <&kp A>;\n    \
+                  };\n  };\n};\n"
+            .to_owned();
+
+    let config = Config::builder().layout("kinesis:adv360").build();
+    let formatted = print(&source, &config).unwrap();
+    assert_eq!(formatted.diagnostics.len(), 1);
+
+    let config = Config::builder()
+        .layout("kinesis:adv360")
+        .binding_mismatch(BindingMismatch::Error)
+        .build();
+    assert!(print(&source, &config).is_err());
+}
+
+#[test]
+fn test_auto_layout() {
+    let bindings = vec!["&kp A"; 34].join(" ");
+    let source = format!(
+        "/ {{\n  keymap {{\n    compatible = \"zmk,keymap\";\n\n    \
+         layer {{\n      bindings = <{}>;\n    }};\n  }};\n}};\n",
+        bindings
+    );
+
+    let config = Config::builder().layout("auto").build();
+    let formatted = print(&source, &config).unwrap();
+    assert_eq!(formatted.detected_layout.unwrap().layout, "sweep");
+}
+
+#[test]
+fn test_auto_physical_layout() {
+    let keys = ["0 0", "0 100", "300 0", "300 100"]
+        .map(|xy| format!("<&key_physical_attrs 100 100 {} 0 0 0>", xy))
+        .join(", ");
+    let source = format!(
+        "/ {{\n  physical_layout0: physical_layout_0 {{\n    \
+         compatible = \"zmk,physical-layout\";\n    keys = {};\n  }};\n\n  \
+         keymap {{\n    compatible = \"zmk,keymap\";\n\n    layer {{\n      \
+         bindings = <&kp A &kp B &kp C &kp D>;\n    }};\n  }};\n}};\n",
+        keys
+    );
+
+    // The physical layout is preferred over the bundled layout that matches
+    // the file name.
+    let config = Config::builder().layout("auto").build();
+    let path = PathBuf::from("corne.keymap");
+    let formatted = print_file(&source, &config, Some(&path)).unwrap();
+
+    assert_eq!(
+        formatted.detected_layout.unwrap().layout,
+        "auto:physical-layout"
+    );
+    assert!(formatted.text.lines().any(|line| {
+        let line = line.trim();
+        line.starts_with("&kp A") && line.ends_with("&kp C")
+    }));
+}
+
+#[test]
+fn test_macro_diagnostic_positions() {
+    let source = "/ {\n  keymap {\n    ZMK_BEHAVIOR(layer, keymap,\n      \
+                  bindings = <&kp A>;\n    )\n  };\n};\n"
+        .to_owned();
+
+    let config = Config::builder().layout("kinesis:adv360").build();
+    let formatted = print(&source, &config).unwrap();
+
+    // The mismatch is reported where the bindings are in the file rather
+    // than in the body of the macro call on its own.
+    let diagnostic = &formatted.diagnostics[0];
+    assert_eq!((diagnostic.line, diagnostic.column), (4, 7));
+    assert_eq!(diagnostic.snippet, "      bindings = <&kp A>;");
+}